@@ -0,0 +1,76 @@
+use super::TokenVerifier;
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use std::time::Duration;
+
+#[derive(Clone)]
+struct AdminState {
+    verifier: TokenVerifier,
+    max_staleness: Option<Duration>,
+}
+
+/// Builds a router of JSON endpoints for inspecting and operating the App Check key set, to be
+/// nested under an internal path of the application router:
+/// * `GET /status` reports the loaded kids and refresh health of the key set
+/// * `POST /refresh` forces an immediate refresh of the key set, bypassing the refresh cooldown
+/// * `GET /ready` fails when the key set is empty or hasn't been refreshed within
+///   `max_staleness`, if configured
+pub fn router<S>(verifier: TokenVerifier, max_staleness: Option<Duration>) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    Router::new()
+        .route("/status", get(status))
+        .route("/refresh", post(refresh))
+        .route("/ready", get(ready))
+        .with_state(AdminState {
+            verifier,
+            max_staleness,
+        })
+}
+
+async fn status(State(state): State<AdminState>) -> Response {
+    Json(state.verifier.status()).into_response()
+}
+
+async fn refresh(State(state): State<AdminState>) -> Response {
+    match state.verifier.refresh_trigger() {
+        Some(trigger) => {
+            trigger.force();
+            tracing::info!("jwk set refresh forced by admin");
+            json_response(StatusCode::ACCEPTED, "accepted", "refresh requested")
+        }
+        None => json_response(
+            StatusCode::CONFLICT,
+            "fail",
+            "verifier is not backed by a refreshing cache",
+        ),
+    }
+}
+
+async fn ready(State(state): State<AdminState>) -> Response {
+    let status = state.verifier.status();
+    if status.kids.is_empty() {
+        return json_response(StatusCode::SERVICE_UNAVAILABLE, "fail", "key set is empty");
+    }
+    if state
+        .max_staleness
+        .is_some_and(|max_staleness| status.is_stale(max_staleness))
+    {
+        return json_response(StatusCode::SERVICE_UNAVAILABLE, "fail", "key set is stale");
+    }
+    json_response(StatusCode::OK, "ok", "key set is ready")
+}
+
+fn json_response(status_code: StatusCode, status: &str, message: &str) -> Response {
+    let resp = serde_json::json!({
+        "status": status,
+        "message": message,
+    });
+    (status_code, Json(resp)).into_response()
+}
@@ -0,0 +1,121 @@
+use super::AppCheckProject;
+use axum::{
+    extract::{FromRequestParts, OptionalFromRequestParts},
+    http::{request::Parts, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use jwt_simple::claims::{Audiences, JWTClaims, NoCustomClaims};
+use std::{
+    convert::Infallible,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// The claims of a verified App Check token, inserted into the request extensions by
+/// `AppCheckService` and extractable in handlers
+#[derive(Clone, Debug)]
+pub struct AppCheckClaims {
+    claims: JWTClaims<NoCustomClaims>,
+    project: Option<AppCheckProject>,
+}
+
+impl AppCheckClaims {
+    pub(crate) fn new(claims: JWTClaims<NoCustomClaims>, project: Option<AppCheckProject>) -> Self {
+        Self { claims, project }
+    }
+
+    /// The Firebase app ID the token was issued to, from the `sub` claim
+    pub fn app_id(&self) -> Option<&str> {
+        self.claims.subject.as_deref()
+    }
+
+    /// The number of the Firebase project the token was issued for; the matched project of a
+    /// verifier serving several, otherwise the `projects/<number>` audience
+    pub fn project_number(&self) -> Option<u64> {
+        if let Some(project) = self.project {
+            return Some(project.project_num);
+        }
+        let project_number = |audience: &str| {
+            audience
+                .strip_prefix("projects/")
+                .and_then(|project| project.parse().ok())
+        };
+        match self.claims.audiences.as_ref()? {
+            Audiences::AsString(audience) => project_number(audience),
+            Audiences::AsSet(audiences) => audiences
+                .iter()
+                .find_map(|audience| project_number(audience)),
+        }
+    }
+
+    pub fn issued_at(&self) -> Option<SystemTime> {
+        self.claims
+            .issued_at
+            .map(|issued_at| UNIX_EPOCH + Duration::from_secs(issued_at.as_secs()))
+    }
+
+    pub fn expires_at(&self) -> Option<SystemTime> {
+        self.claims
+            .expires_at
+            .map(|expires_at| UNIX_EPOCH + Duration::from_secs(expires_at.as_secs()))
+    }
+
+    /// The unique identifier of the token, from the `jti` claim
+    pub fn token_id(&self) -> Option<&str> {
+        self.claims.jwt_id.as_deref()
+    }
+
+    pub fn claims(&self) -> &JWTClaims<NoCustomClaims> {
+        &self.claims
+    }
+
+    pub fn into_claims(self) -> JWTClaims<NoCustomClaims> {
+        self.claims
+    }
+}
+
+impl<S> FromRequestParts<S> for AppCheckClaims
+where
+    S: Send + Sync,
+{
+    type Rejection = MissingAppCheckClaims;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<Self>()
+            .cloned()
+            .ok_or(MissingAppCheckClaims)
+    }
+}
+
+impl<S> OptionalFromRequestParts<S> for AppCheckClaims
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        _state: &S,
+    ) -> Result<Option<Self>, Self::Rejection> {
+        Ok(parts.extensions.get::<Self>().cloned())
+    }
+}
+
+/// Rejection for extracting `AppCheckClaims` from a request that wasn't authorized with an App
+/// Check token, either because the route isn't behind an `AppCheckLayer` or because it was
+/// authorized with a bearer token instead
+#[derive(Debug)]
+pub struct MissingAppCheckClaims;
+
+impl IntoResponse for MissingAppCheckClaims {
+    fn into_response(self) -> Response {
+        tracing::debug!("request missing app check claims");
+        let err_resp = serde_json::json!({
+            "status": "fail",
+            "message": "request not authenticated with app check",
+        });
+        (StatusCode::UNAUTHORIZED, Json(err_resp)).into_response()
+    }
+}
@@ -0,0 +1,198 @@
+use super::Error;
+use jwt_simple::{
+    algorithms::{RS256KeyPair, RSAKeyPairLike},
+    claims::Claims,
+    prelude::Duration,
+};
+use serde::{Deserialize, Serialize};
+use std::{path::Path, sync::Arc};
+use tokio::{sync::Mutex, time::Instant};
+
+const DEFAULT_BASE_URL: &str = "https://firebaseappcheck.googleapis.com";
+const DEFAULT_TOKEN_URI: &str = "https://oauth2.googleapis.com/token";
+const FIREBASE_SCOPE: &str = "https://www.googleapis.com/auth/firebase";
+// Access tokens are renewed this long before they expire so they don't lapse in flight
+const ACCESS_TOKEN_MARGIN: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// The outcome of consuming a verified token with the Firebase App Check API, inserted into the
+/// request extensions
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TokenConsumption {
+    /// Whether the token had already been consumed by an earlier request
+    pub already_consumed: bool,
+}
+
+/// The fields of a Google service account key file used to authenticate with the API
+#[derive(Clone, Deserialize)]
+pub struct ServiceAccountKey {
+    pub client_email: String,
+    pub private_key: String,
+    #[serde(default = "default_token_uri")]
+    pub token_uri: String,
+}
+
+fn default_token_uri() -> String {
+    DEFAULT_TOKEN_URI.to_string()
+}
+
+/// Marks verified tokens as consumed with the Firebase App Check `verifyAppCheckToken` API, the
+/// server-side replay protection for limited-use tokens
+#[derive(Clone)]
+pub struct TokenConsumer {
+    client: reqwest::Client,
+    base_url: String,
+    client_email: String,
+    token_uri: String,
+    key_pair: RS256KeyPair,
+    access_token: Arc<Mutex<Option<(String, Instant)>>>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct VerifyRequest<'a> {
+    app_check_token: &'a str,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct VerifyResponse {
+    #[serde(default)]
+    already_consumed: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct AssertionClaims {
+    scope: String,
+}
+
+#[derive(Deserialize)]
+struct AccessTokenResponse {
+    access_token: String,
+    expires_in: u64,
+}
+
+impl TokenConsumer {
+    pub fn new(client: reqwest::Client, key: ServiceAccountKey) -> Result<Self, Error> {
+        let key_pair = RS256KeyPair::from_pem(&key.private_key)
+            .map_err(|err| Error::InvalidCredentials(err.to_string()))?;
+        Ok(Self {
+            client,
+            base_url: DEFAULT_BASE_URL.to_string(),
+            client_email: key.client_email,
+            token_uri: key.token_uri,
+            key_pair,
+            access_token: Arc::default(),
+        })
+    }
+
+    /// Reads the service account key from a JSON key file
+    pub async fn from_key_file(
+        client: reqwest::Client,
+        path: impl AsRef<Path>,
+    ) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let key = tokio::fs::read(path)
+            .await
+            .map_err(|err| Error::InvalidCredentials(format!("{}: {err}", path.display())))?;
+        let key = serde_json::from_slice(&key)
+            .map_err(|err| Error::InvalidCredentials(err.to_string()))?;
+        Self::new(client, key)
+    }
+
+    /// Call the API at `base_url` in place of the Firebase App Check endpoint, e.g. a mock server
+    pub fn with_base_url(self, base_url: impl Into<String>) -> Self {
+        Self {
+            base_url: base_url.into(),
+            ..self
+        }
+    }
+
+    /// Consumes the token for the project, reporting whether it was already consumed
+    pub async fn consume(
+        &self,
+        project_number: u64,
+        token: &str,
+    ) -> Result<TokenConsumption, Error> {
+        let access_token = self.access_token().await?;
+        let url = format!(
+            "{}/v1beta/projects/{project_number}:verifyAppCheckToken",
+            self.base_url.trim_end_matches('/')
+        );
+        let response: VerifyResponse = self
+            .client
+            .post(url)
+            .bearer_auth(access_token)
+            .json(&VerifyRequest {
+                app_check_token: token,
+            })
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|err| Error::ConsumeToken(err.to_string()))?
+            .json()
+            .await
+            .map_err(|err| Error::ConsumeToken(err.to_string()))?;
+        Ok(TokenConsumption {
+            already_consumed: response.already_consumed,
+        })
+    }
+
+    // Returns the cached access token, exchanging a signed assertion for a new one at the
+    // token URI once it's about to expire
+    async fn access_token(&self) -> Result<String, Error> {
+        let mut cached = self.access_token.lock().await;
+        if let Some((ref token, expiry)) = *cached {
+            if Instant::now() + ACCESS_TOKEN_MARGIN < expiry {
+                return Ok(token.clone());
+            }
+        }
+
+        let claims = Claims::with_custom_claims(
+            AssertionClaims {
+                scope: FIREBASE_SCOPE.to_string(),
+            },
+            Duration::from_hours(1),
+        )
+        .with_issuer(&self.client_email)
+        .with_audience(&self.token_uri);
+        let assertion = self
+            .key_pair
+            .sign(claims)
+            .map_err(|err| Error::InvalidCredentials(err.to_string()))?;
+
+        let response: AccessTokenResponse = self
+            .client
+            .post(&self.token_uri)
+            .form(&[
+                ("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer"),
+                ("assertion", assertion.as_str()),
+            ])
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|err| Error::ConsumeToken(err.to_string()))?
+            .json()
+            .await
+            .map_err(|err| Error::ConsumeToken(err.to_string()))?;
+        tracing::debug!(
+            expires_in = response.expires_in,
+            "obtained api access token"
+        );
+
+        let expiry = Instant::now() + std::time::Duration::from_secs(response.expires_in);
+        *cached = Some((response.access_token.clone(), expiry));
+        Ok(response.access_token)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn missing_key_file_is_invalid_credentials() {
+        let result =
+            TokenConsumer::from_key_file(reqwest::Client::new(), "/nonexistent/key.json").await;
+        assert!(matches!(result, Err(Error::InvalidCredentials(_))));
+    }
+}
@@ -0,0 +1,15 @@
+use serde::{Deserialize, Serialize};
+
+/// The custom claims of a Firebase Authentication ID token
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct IdTokenClaims {
+    /// When the user authenticated, in seconds since the unix epoch
+    pub auth_time: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email_verified: Option<bool>,
+    /// Sign-in provider and linked identities of the user
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub firebase: Option<serde_json::Value>,
+}
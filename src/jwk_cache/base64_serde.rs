@@ -1,23 +1,81 @@
-use base64::{engine::general_purpose::URL_SAFE, Engine};
+use base64::{
+    alphabet,
+    engine::{
+        general_purpose::{GeneralPurpose, GeneralPurposeConfig, URL_SAFE_NO_PAD},
+        DecodePaddingMode,
+    },
+    Engine,
+};
 use serde::{Deserialize, Deserializer, Serializer};
 
+// JWKs encode key components as unpadded base64url, though some issuers include the padding
+const URL_SAFE_LENIENT: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::URL_SAFE,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
 pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<u8>, D::Error>
 where
     D: Deserializer<'de>,
 {
     use serde::de::Error;
     String::deserialize(deserializer).and_then(|string| {
-        URL_SAFE
+        URL_SAFE_LENIENT
             .decode(string)
             .map_err(|err| Error::custom(err.to_string()))
     })
 }
 
-#[allow(dead_code)]
 pub fn serialize<S, B>(bytes: B, serializer: S) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
     B: AsRef<[u8]>,
 {
-    serializer.serialize_str(&URL_SAFE.encode(bytes.as_ref()))
+    serializer.serialize_str(&URL_SAFE_NO_PAD.encode(bytes.as_ref()))
+}
+
+pub mod option {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    #[derive(Deserialize)]
+    struct Bytes(#[serde(with = "super")] Vec<u8>);
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<Vec<u8>>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Option::<Bytes>::deserialize(deserializer).map(|bytes| bytes.map(|Bytes(bytes)| bytes))
+    }
+
+    pub fn serialize<S>(bytes: &Option<Vec<u8>>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match bytes {
+            Some(bytes) => super::serialize(bytes, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Deserialize, Serialize, PartialEq)]
+    struct Component(#[serde(with = "super")] Vec<u8>);
+
+    #[test]
+    fn serializes_unpadded_base64url() {
+        let json = serde_json::to_string(&Component(vec![0xfb, 0xff])).unwrap();
+        assert_eq!(json, r#""-_8""#);
+    }
+
+    #[test]
+    fn deserializes_with_or_without_padding() {
+        for json in [r#""-_8""#, r#""-_8=""#] {
+            let component: Component = serde_json::from_str(json).unwrap();
+            assert_eq!(component, Component(vec![0xfb, 0xff]));
+        }
+    }
 }
@@ -0,0 +1,161 @@
+use super::{
+    jwk_set::{self, KeyFormat, KeySetUpdate, Validators},
+    source::KeySource,
+    Duration, Error, Instant,
+};
+use futures_util::future::BoxFuture;
+use serde::Deserialize;
+use std::sync::Mutex;
+use tokio::sync::watch;
+
+const DEFAULT_RECHECK: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Fetches keys from the `jwks_uri` of an OpenID Connect discovery document, re-reading the
+/// document every `recheck` interval to follow changes to the jwks URI and issuer
+pub struct DiscoveryKeySource {
+    client: reqwest::Client,
+    url: String,
+    recheck: Duration,
+    resolved: Mutex<Option<Resolved>>,
+    // The jwks URI and validators of the last fetch until the cache accepts its key set
+    fetched: Mutex<Option<(String, Validators)>>,
+    issuer: watch::Sender<Option<String>>,
+}
+
+#[derive(Clone)]
+struct Resolved {
+    jwks_uri: String,
+    validators: Validators,
+    at: Instant,
+}
+
+// The fields of an OpenID Provider Metadata document used to verify tokens
+#[derive(Deserialize)]
+struct Discovery {
+    issuer: String,
+    jwks_uri: String,
+}
+
+impl DiscoveryKeySource {
+    pub fn new(client: reqwest::Client, url: String) -> Self {
+        Self {
+            client,
+            url,
+            recheck: DEFAULT_RECHECK,
+            resolved: Mutex::default(),
+            fetched: Mutex::default(),
+            issuer: watch::channel(None).0,
+        }
+    }
+
+    pub fn with_recheck(self, recheck: Duration) -> Self {
+        Self { recheck, ..self }
+    }
+
+    /// Receives the issuer of the last successfully fetched discovery document
+    pub fn issuer(&self) -> watch::Receiver<Option<String>> {
+        self.issuer.subscribe()
+    }
+
+    // Returns the jwks URI to fetch, re-reading the discovery document when the last one was
+    // read longer than `recheck` ago. A failed re-check keeps using the previous jwks URI.
+    async fn resolve(&self) -> Result<(String, Validators), Error> {
+        let current = self
+            .resolved
+            .lock()
+            .ok()
+            .and_then(|resolved| resolved.clone());
+        if let Some(ref resolved) = current {
+            if resolved.at.elapsed() < self.recheck {
+                return Ok((resolved.jwks_uri.clone(), resolved.validators.clone()));
+            }
+        }
+
+        let discovery = match fetch_discovery(&self.client, &self.url).await {
+            Ok(discovery) => discovery,
+            Err(err) => {
+                return match current {
+                    Some(resolved) => {
+                        tracing::warn!(?err, url = self.url, "failed to re-check oidc discovery");
+                        Ok((resolved.jwks_uri, resolved.validators))
+                    }
+                    None => Err(err),
+                };
+            }
+        };
+
+        self.issuer.send_if_modified(|issuer| {
+            if issuer.as_deref() == Some(discovery.issuer.as_str()) {
+                return false;
+            }
+            tracing::info!(issuer = discovery.issuer, "discovered token issuer");
+            *issuer = Some(discovery.issuer.clone());
+            true
+        });
+        // Validators only apply to revalidating the jwks URI they were received from
+        let validators = current
+            .filter(|resolved| resolved.jwks_uri == discovery.jwks_uri)
+            .map(|resolved| resolved.validators)
+            .unwrap_or_default();
+        if let Ok(mut resolved) = self.resolved.lock() {
+            *resolved = Some(Resolved {
+                jwks_uri: discovery.jwks_uri.clone(),
+                validators: validators.clone(),
+                at: Instant::now(),
+            });
+        }
+        Ok((discovery.jwks_uri, validators))
+    }
+}
+
+impl KeySource for DiscoveryKeySource {
+    fn fetch(&self) -> BoxFuture<'_, Result<KeySetUpdate, Error>> {
+        Box::pin(async move {
+            let (jwks_uri, validators) = self.resolve().await?;
+            let (update, validators) =
+                jwk_set::fetch_key_set(&self.client, &jwks_uri, KeyFormat::Jwks, validators)
+                    .await?;
+            if let Ok(mut fetched) = self.fetched.lock() {
+                *fetched = Some((jwks_uri, validators));
+            }
+            Ok(update)
+        })
+    }
+
+    fn name(&self) -> &str {
+        &self.url
+    }
+
+    fn commit(&self) {
+        let fetched = self
+            .fetched
+            .lock()
+            .ok()
+            .and_then(|mut fetched| fetched.take());
+        let (Some((jwks_uri, validators)), Ok(mut resolved)) = (fetched, self.resolved.lock())
+        else {
+            return;
+        };
+        if let Some(resolved) = resolved
+            .as_mut()
+            .filter(|resolved| resolved.jwks_uri == jwks_uri)
+        {
+            resolved.validators = validators;
+        }
+    }
+}
+
+async fn fetch_discovery(client: &reqwest::Client, url: &str) -> Result<Discovery, Error> {
+    let discovery = client
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()
+        .map_err(|err| {
+            tracing::info!(?err, "failed to retrieve oidc discovery document");
+            err
+        })?
+        .json::<Discovery>()
+        .await?;
+    Ok(discovery)
+}
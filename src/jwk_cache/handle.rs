@@ -0,0 +1,38 @@
+use super::RefreshTrigger;
+use tokio::task::{JoinError, JoinHandle};
+
+/// Handle to a `JwkCache` spawned onto the Tokio runtime
+pub struct JwkCacheHandle {
+    task: JoinHandle<()>,
+    trigger: RefreshTrigger,
+}
+
+impl JwkCacheHandle {
+    pub(super) fn new(task: JoinHandle<()>, trigger: RefreshTrigger) -> Self {
+        Self { task, trigger }
+    }
+
+    /// Requests an immediate refresh of the key set, deferred to the end of the refresh cooldown
+    /// if one completed within it
+    pub fn refresh(&self) {
+        self.trigger.trigger();
+    }
+
+    /// Requests an immediate refresh of the key set regardless of the refresh cooldown
+    pub fn force_refresh(&self) {
+        self.trigger.force();
+    }
+
+    pub fn refresh_trigger(&self) -> RefreshTrigger {
+        self.trigger.clone()
+    }
+
+    /// Waits for the cache task to stop after its shutdown signal resolves
+    pub async fn join(self) -> Result<(), JoinError> {
+        self.task.await
+    }
+
+    pub fn into_join_handle(self) -> JoinHandle<()> {
+        self.task
+    }
+}
@@ -1,49 +1,437 @@
-use super::{base64_serde, Error, HashMap, RS256PublicKey};
-use serde::Deserialize;
+use super::{base64_serde, x509, Algorithm, Duration, Error, HashMap, HashSet, VerifyingKey};
+use jwt_simple::algorithms::{
+    ES256PublicKey, Ed25519PublicKey, PS256PublicKey, RS256PublicKey, RS384PublicKey,
+    RS512PublicKey,
+};
+use reqwest::{
+    header::{
+        HeaderMap, HeaderValue, AGE, CACHE_CONTROL, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH,
+        LAST_MODIFIED,
+    },
+    StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::{fs, io::AsyncWriteExt};
 
-const KTY: &str = "RSA";
-const ALG: &str = "RS256";
+/// The form in which a source publishes its keys
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyFormat {
+    /// A jwks document
+    #[default]
+    Jwks,
+    /// A map of kid to PEM encoded x509 certificate, the form in which the keys signing Firebase
+    /// Authentication ID tokens are published
+    X509,
+}
+
+/// A set of public keys indexed by kid as retrieved from a `KeySource`
+pub struct KeySet {
+    pub keys: HashMap<String, VerifyingKey>,
+    /// How long the key set may be cached before it should be fetched again
+    pub max_age: Option<Duration>,
+    // The compatible keys as published when parsed from a jwks document, for persisting to disk
+    pub(super) jwk_set: Option<JwkSet>,
+}
+
+impl KeySet {
+    pub fn new(keys: HashMap<String, VerifyingKey>) -> Self {
+        Self {
+            keys,
+            max_age: None,
+            jwk_set: None,
+        }
+    }
+
+    // Drops the keys for algorithms outside `algorithms`, from the published set persisted to
+    // the key file as well as from the verifying keys
+    pub(super) fn retain_algorithms(&mut self, algorithms: &HashSet<Algorithm>) {
+        self.keys
+            .retain(|_, key| algorithms.contains(&key.algorithm()));
+        if let Some(jwk_set) = &mut self.jwk_set {
+            jwk_set
+                .keys
+                .retain(|jwk| jwk.algorithm().is_some_and(|alg| algorithms.contains(&alg)));
+        }
+    }
+
+    /// Parses a document of keys published in `format`
+    pub fn parse(document: &[u8], format: KeyFormat) -> Result<Self, Error> {
+        match format {
+            KeyFormat::Jwks => Self::from_jwks(document),
+            KeyFormat::X509 => Ok(Self::from_x509_certs(serde_json::from_slice(document)?)),
+        }
+    }
+
+    /// Parses a jwks document, keeping only the signing keys for supported algorithms. Keys that
+    /// are malformed, unsupported or not for signatures are skipped rather than failing the set.
+    pub fn from_jwks(jwks: &[u8]) -> Result<Self, Error> {
+        Ok(Self::from_jwk_values(
+            serde_json::from_slice::<RawJwkSet>(jwks)?.keys,
+        ))
+    }
+
+    fn from_jwk_values(values: Vec<serde_json::Value>) -> Self {
+        let mut keys = HashMap::new();
+        let mut jwk_set = JwkSet { keys: Vec::new() };
+
+        for value in values {
+            let jwk = match serde_json::from_value::<Jwk>(value) {
+                Ok(jwk) => jwk,
+                Err(err) => {
+                    skip_key("malformed", None, &err.to_string());
+                    continue;
+                }
+            };
+            if jwk.r#use.as_deref().is_some_and(|key_use| key_use != "sig") {
+                skip_key("not-sig", Some(&jwk.kid), "key is not for signatures");
+                continue;
+            }
+            if jwk.algorithm().is_none() {
+                skip_key("unsupported", Some(&jwk.kid), "unsupported key algorithm");
+                continue;
+            }
+            match jwk.verifying_key() {
+                Ok(key) => {
+                    keys.insert(jwk.kid.clone(), key);
+                    jwk_set.keys.push(jwk);
+                }
+                Err(err) => skip_key("invalid", Some(&jwk.kid), &err.to_string()),
+            }
+        }
+
+        Self {
+            keys,
+            max_age: None,
+            jwk_set: Some(jwk_set),
+        }
+    }
+
+    /// Extracts the RS256 public keys of x509 certificates indexed by kid, skipping any that
+    /// can't be parsed. Certificate key sets have no jwks form, so aren't persisted to disk.
+    pub fn from_x509_certs(certs: HashMap<String, String>) -> Self {
+        let mut keys = HashMap::new();
+        for (kid, cert) in certs {
+            let key = x509::public_key_der(&cert)
+                .ok_or_else(|| Error::InvalidJwk(kid.clone()))
+                .and_then(|der| Ok(RS256PublicKey::from_der(&der)?));
+            match key {
+                Ok(key) => {
+                    keys.insert(kid, key.into());
+                }
+                Err(err) => skip_key("invalid", Some(&kid), &err.to_string()),
+            }
+        }
+        Self::new(keys)
+    }
+
+    pub fn with_max_age(self, max_age: Option<Duration>) -> Self {
+        Self { max_age, ..self }
+    }
+}
+
+fn skip_key(reason: &'static str, key_id: Option<&str>, err: &str) {
+    metrics::counter!("appcheck-jwk-skipped", "reason" => reason).increment(1);
+    tracing::warn!(reason, key_id, err, "skipping jwk");
+}
+
+/// The result of fetching a key set from a `KeySource`
+pub enum KeySetUpdate {
+    /// The source returned a new or changed key set
+    Changed(KeySet),
+    /// The source reported the key set is unchanged since the last fetch; the current keys
+    /// may be cached for another `max_age`
+    Unchanged { max_age: Option<Duration> },
+}
+
+/// Validators of the last fetched jwks response, for making conditional requests
+#[derive(Clone, Debug, Default)]
+pub(super) struct Validators {
+    etag: Option<HeaderValue>,
+    last_modified: Option<HeaderValue>,
+}
 
 pub(super) async fn fetch_key_set(
     client: &reqwest::Client,
     url: &str,
-) -> Result<HashMap<String, RS256PublicKey>, Error> {
-    client
-        .get(url)
-        .send()
-        .await?
-        .error_for_status()
-        .map_err(|err| {
-            tracing::info!(?err, "failed to retrieve firebase jwk set");
-            err
-        })?
-        .json::<JwkSet>()
-        .await?
-        .keys
-        .into_iter()
-        .filter(|key| key.alg == ALG && key.kty == KTY)
-        .try_fold(HashMap::new(), |mut set, key| {
-            let pub_key = RS256PublicKey::from_components(&key.n, &key.e)?;
-            tracing::info!(key_id = %key.kid, "adding public key to validation cache");
-            set.insert(key.kid, pub_key);
-            Ok(set)
-        })
+    format: KeyFormat,
+    validators: Validators,
+) -> Result<(KeySetUpdate, Validators), Error> {
+    let request = client.get(url);
+    let request = if let Some(ref etag) = validators.etag {
+        request.header(IF_NONE_MATCH, etag)
+    } else {
+        request
+    };
+    let request = if let Some(ref last_modified) = validators.last_modified {
+        request.header(IF_MODIFIED_SINCE, last_modified)
+    } else {
+        request
+    };
+
+    let response = request.send().await?.error_for_status().map_err(|err| {
+        tracing::info!(?err, "failed to retrieve firebase jwk set");
+        err
+    })?;
+
+    let max_age = max_age(response.headers());
+
+    if response.status() == StatusCode::NOT_MODIFIED {
+        tracing::debug!(url, "jwk set not modified");
+        return Ok((KeySetUpdate::Unchanged { max_age }, validators));
+    }
+
+    let validators = Validators {
+        etag: response.headers().get(ETAG).cloned(),
+        last_modified: response.headers().get(LAST_MODIFIED).cloned(),
+    };
+    let key_set = KeySet::parse(&response.bytes().await?, format)?.with_max_age(max_age);
+
+    Ok((KeySetUpdate::Changed(key_set), validators))
 }
 
-#[derive(Debug, Deserialize)]
-struct Jwk {
+/// Loads a key set previously written by `store_key_set`, rejecting it when it was fetched
+/// longer than `max_age` ago
+pub(super) async fn load_key_set(path: &Path, max_age: Duration) -> Result<KeySet, Error> {
+    let stored: StoredKeySet = serde_json::from_slice(&fs::read(path).await?)?;
+    let age = unix_now().saturating_sub(stored.fetched_at);
+    if age > max_age.as_secs() {
+        return Err(Error::JwkFileExpired(age));
+    }
+    tracing::info!(?path, age, "loading stored jwk set");
+    Ok(KeySet {
+        keys: stored.jwk_set.public_keys()?,
+        max_age: None,
+        jwk_set: Some(stored.jwk_set),
+    })
+}
+
+/// Atomically replaces the file at `path` with the key set by writing and syncing a temporary
+/// sibling file before renaming it into place
+pub(super) async fn store_key_set(path: &Path, jwk_set: &JwkSet) -> Result<(), Error> {
+    let stored = serde_json::to_vec(&StoredKeySetRef {
+        fetched_at: unix_now(),
+        jwk_set,
+    })?;
+    // Appended rather than swapping the extension, which would be the key file itself if it
+    // were already named `.tmp`
+    let tmp_path = PathBuf::from(format!("{}.tmp", path.display()));
+    let mut file = fs::File::create(&tmp_path).await?;
+    file.write_all(&stored).await?;
+    file.sync_all().await?;
+    fs::rename(&tmp_path, path).await?;
+    Ok(())
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs())
+        .unwrap_or_default()
+}
+
+// Computes the remaining freshness lifetime from the `max-age` directive less any `Age`
+// already accrued in intermediate caches; `no-cache` and `no-store` are treated as stale
+fn max_age(headers: &HeaderMap) -> Option<Duration> {
+    let max_age = headers
+        .get_all(CACHE_CONTROL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|directive| directive.trim().to_ascii_lowercase())
+        .find_map(|directive| match directive.as_str() {
+            "no-cache" | "no-store" => Some(0),
+            _ => directive
+                .strip_prefix("max-age=")
+                .and_then(|secs| secs.trim_matches('"').parse::<u64>().ok()),
+        })?;
+
+    let age = headers
+        .get(AGE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<u64>().ok())
+        .unwrap_or_default();
+
+    Some(Duration::from_secs(max_age.saturating_sub(age)))
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub(super) struct Jwk {
     kty: String,
-    #[allow(dead_code)]
-    r#use: String,
-    alg: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    r#use: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    alg: Option<String>,
     kid: String,
-    #[serde(with = "base64_serde")]
-    n: Vec<u8>,
-    #[serde(with = "base64_serde")]
-    e: Vec<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    crv: Option<String>,
+    #[serde(
+        default,
+        with = "base64_serde::option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    n: Option<Vec<u8>>,
+    #[serde(
+        default,
+        with = "base64_serde::option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    e: Option<Vec<u8>>,
+    #[serde(
+        default,
+        with = "base64_serde::option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    x: Option<Vec<u8>>,
+    #[serde(
+        default,
+        with = "base64_serde::option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    y: Option<Vec<u8>>,
 }
 
-#[derive(Debug, Deserialize)]
-struct JwkSet {
+impl Jwk {
+    // The algorithm the key is published for, inferred from the curve of EC and OKP keys
+    // without an `alg`; RSA keys must declare theirs as several algorithms share the key type
+    fn algorithm(&self) -> Option<Algorithm> {
+        match (self.kty.as_str(), self.alg.as_deref(), self.crv.as_deref()) {
+            (_, Some(alg), _) => Algorithm::from_name(alg),
+            ("EC", None, Some("P-256")) => Some(Algorithm::ES256),
+            ("OKP", None, Some("Ed25519")) => Some(Algorithm::EdDSA),
+            _ => None,
+        }
+    }
+
+    fn verifying_key(&self) -> Result<VerifyingKey, Error> {
+        let invalid = || Error::InvalidJwk(self.kid.clone());
+        let component = |value: &Option<Vec<u8>>| value.clone().ok_or_else(invalid);
+        let key = match (self.algorithm(), self.kty.as_str(), self.crv.as_deref()) {
+            (Some(Algorithm::RS256), "RSA", _) => {
+                RS256PublicKey::from_components(&component(&self.n)?, &component(&self.e)?)?.into()
+            }
+            (Some(Algorithm::RS384), "RSA", _) => {
+                RS384PublicKey::from_components(&component(&self.n)?, &component(&self.e)?)?.into()
+            }
+            (Some(Algorithm::RS512), "RSA", _) => {
+                RS512PublicKey::from_components(&component(&self.n)?, &component(&self.e)?)?.into()
+            }
+            (Some(Algorithm::PS256), "RSA", _) => {
+                PS256PublicKey::from_components(&component(&self.n)?, &component(&self.e)?)?.into()
+            }
+            (Some(Algorithm::ES256), "EC", Some("P-256")) => {
+                // Uncompressed SEC1 encoding of the curve point
+                let mut point = vec![0x04];
+                point.extend(component(&self.x)?);
+                point.extend(component(&self.y)?);
+                ES256PublicKey::from_bytes(&point)?.into()
+            }
+            (Some(Algorithm::EdDSA), "OKP", Some("Ed25519")) => {
+                Ed25519PublicKey::from_bytes(&component(&self.x)?)?.into()
+            }
+            _ => return Err(invalid()),
+        };
+        Ok(key)
+    }
+}
+
+// A jwks document with its keys left unparsed so each can be parsed independently
+#[derive(Deserialize)]
+struct RawJwkSet {
+    keys: Vec<serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub(super) struct JwkSet {
     keys: Vec<Jwk>,
 }
+
+impl JwkSet {
+    fn public_keys(&self) -> Result<HashMap<String, VerifyingKey>, Error> {
+        self.keys.iter().try_fold(HashMap::new(), |mut set, key| {
+            set.insert(key.kid.clone(), key.verifying_key()?);
+            Ok(set)
+        })
+    }
+}
+
+// The on-disk form of a key set is the jwks document itself annotated with the unix time at
+// which it was fetched
+#[derive(Deserialize)]
+struct StoredKeySet {
+    fetched_at: u64,
+    #[serde(flatten)]
+    jwk_set: JwkSet,
+}
+
+#[derive(Serialize)]
+struct StoredKeySetRef<'a> {
+    fetched_at: u64,
+    #[serde(flatten)]
+    jwk_set: &'a JwkSet,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_documents_in_the_configured_format_only() {
+        let jwks = br#"{"keys": []}"#;
+        let certs = br#"{"kid": "-----BEGIN CERTIFICATE-----"}"#;
+
+        assert!(KeySet::parse(jwks, KeyFormat::Jwks).is_ok());
+        assert!(matches!(
+            KeySet::parse(jwks, KeyFormat::X509),
+            Err(Error::JwkSerde(_))
+        ));
+        assert!(matches!(
+            KeySet::parse(certs, KeyFormat::Jwks),
+            Err(Error::JwkSerde(_))
+        ));
+        // The certificate can't be parsed, so is skipped
+        assert!(KeySet::parse(certs, KeyFormat::X509)
+            .unwrap()
+            .keys
+            .is_empty());
+    }
+
+    #[test]
+    fn skips_unusable_keys_of_a_jwks_document() {
+        // The modulus of the RSA 2048 key in the x509 tests
+        let n = "vaAj7nTPT4t-xL7ILzCwy1UW1PKEV9oD7lOfkkEA-7nFxMbBvSYCvAzbZPat1O0xntAZqTvvFDfgjVFVBTTUDS0Zz7WHxoviykeFTJLlurhP0rz7QuejAmZUgX6CxTu7zXURnfFZ0-QF5uFWpOm-ogGONPu7qhRDlwQ38FEfbHqY3QkOKK8TSg_2t86xKfGMyiaEUgfQyQUex2Vurgbn9f9JzXivpokaJFB8YTRe_P0cD0BiS2_PMvIkzLlP5njoumwh_MhBGYa8oO2kI6D6za3rZIcbHeB2eCWWI3DS3KyjVo8nA6R9oVYZ0N7nUVuP_c3vk8K_QFg_E2uPml7RdQ";
+        let jwks = serde_json::json!({
+            "keys": [
+                {"kty": "RSA", "alg": "RS256", "kid": "valid", "n": n, "e": "AQAB"},
+                {"kty": "RSA", "alg": "RS256", "use": "enc", "kid": "enc", "n": n, "e": "AQAB"},
+                {"kty": "RSA", "alg": "RS256", "kid": "malformed", "n": "not base64!", "e": "AQAB"},
+                {"kty": "RSA", "alg": "HS256", "kid": "unsupported", "n": n, "e": "AQAB"},
+            ]
+        });
+
+        let key_set = KeySet::from_jwks(jwks.to_string().as_bytes()).unwrap();
+        assert_eq!(
+            key_set.keys.keys().collect::<Vec<_>>(),
+            vec![&"valid".to_string()]
+        );
+        let jwk_set = key_set.jwk_set.unwrap();
+        assert_eq!(jwk_set.keys.len(), 1);
+        assert_eq!(jwk_set.keys[0].kid, "valid");
+    }
+
+    #[tokio::test]
+    async fn round_trips_key_set_file_named_tmp() {
+        let path = std::env::temp_dir().join(format!("appcheck-keys-{}.tmp", std::process::id()));
+        let jwk_set = JwkSet { keys: Vec::new() };
+
+        store_key_set(&path, &jwk_set).await.unwrap();
+        let stored = load_key_set(&path, Duration::from_secs(60)).await.unwrap();
+        assert!(stored.keys.is_empty());
+        assert!(!Path::new(&format!("{}.tmp", path.display())).exists());
+        let _ = fs::remove_file(&path).await;
+    }
+}
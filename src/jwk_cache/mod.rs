@@ -1,24 +1,272 @@
 use super::TokenVerifier;
-use crate::{settings::BearerSettings, Error};
-use jwt_simple::{algorithms::RS256PublicKey, common::VerificationOptions};
-use std::collections::{HashMap, HashSet};
+use crate::{
+    settings::{self, BearerSettings, Settings, StartupMode},
+    Error,
+};
+use jwt_simple::common::VerificationOptions;
+use rand::Rng;
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::SystemTime,
+};
 use tokio::{
-    sync::watch,
-    time::{interval, Duration},
+    sync::{broadcast, watch, Notify},
+    time::{sleep_until, Duration, Instant},
 };
-use triggered::Listener;
 
 mod base64_serde;
+mod discovery;
+mod handle;
 mod jwk_set;
+mod rotation;
+mod source;
+mod status;
+mod verifying_key;
+mod x509;
+
+pub use discovery::DiscoveryKeySource;
+pub use handle::JwkCacheHandle;
+pub use jwk_set::{KeyFormat, KeySet, KeySetUpdate};
+pub use rotation::RotationEvent;
+pub use source::{FileKeySource, HttpKeySource, KeySource, StaticKeySource};
+pub use status::KeySetStatus;
+pub use verifying_key::{Algorithm, VerifyingKey};
+
+const DEFAULT_MIN_DURATION: Duration = Duration::from_secs(300);
+const MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+const DEFAULT_REFRESH_COOLDOWN: Duration = Duration::from_secs(30);
+const DEFAULT_RETRY_INITIAL: Duration = Duration::from_secs(5);
+const DEFAULT_RETRY_MAX: Duration = Duration::from_secs(300);
+const ROTATION_EVENTS_CAPACITY: usize = 16;
+
+/// Handle for requesting an out-of-band refresh of the key set from the running `JwkCache`.
+/// Requests made while a refresh is pending are coalesced into a single fetch.
+#[derive(Clone, Debug, Default)]
+pub struct RefreshTrigger {
+    requested: Arc<Notify>,
+    forced: Arc<AtomicBool>,
+}
+
+impl RefreshTrigger {
+    /// Requests a refresh, subject to the refresh cooldown
+    pub fn trigger(&self) {
+        self.requested.notify_one();
+    }
+
+    /// Requests a refresh regardless of the refresh cooldown, e.g. by an operator who knows
+    /// the keys have been rotated
+    pub fn force(&self) {
+        self.forced.store(true, Ordering::Relaxed);
+        self.requested.notify_one();
+    }
+
+    async fn requested(&self) {
+        self.requested.notified().await
+    }
+
+    fn take_forced(&self) -> bool {
+        self.forced.swap(false, Ordering::Relaxed)
+    }
+}
+
+struct RefreshOptions {
+    min_duration: Duration,
+    max_duration: Duration,
+    cooldown: Duration,
+    unknown_kid_wait: Option<Duration>,
+    retry_initial: Duration,
+    retry_max: Duration,
+    startup: StartupMode,
+    key_file: Option<PathBuf>,
+    key_file_max_age: Duration,
+    allow_empty: bool,
+    removed_key_grace: Option<Duration>,
+    algorithms: HashSet<Algorithm>,
+}
+
+impl RefreshOptions {
+    fn with_max_duration(duration: Duration) -> Self {
+        Self {
+            min_duration: match duration {
+                Duration::ZERO => DEFAULT_MIN_DURATION,
+                duration => DEFAULT_MIN_DURATION.min(duration),
+            },
+            max_duration: duration,
+            cooldown: DEFAULT_REFRESH_COOLDOWN,
+            unknown_kid_wait: None,
+            retry_initial: DEFAULT_RETRY_INITIAL,
+            retry_max: DEFAULT_RETRY_MAX,
+            startup: StartupMode::Strict,
+            key_file: None,
+            key_file_max_age: Duration::ZERO,
+            allow_empty: false,
+            removed_key_grace: None,
+            algorithms: HashSet::from([Algorithm::RS256]),
+        }
+    }
+
+    // Exponential backoff from the initial retry delay capped at the max retry delay, jittered
+    // over the upper half of the delay so a fleet of instances doesn't retry in lockstep
+    fn retry_in(&self, consecutive_failures: u32) -> Duration {
+        let exponent = consecutive_failures.saturating_sub(1).min(31);
+        let backoff = self
+            .retry_initial
+            .saturating_mul(1 << exponent)
+            .min(self.retry_max);
+        backoff / 2 + backoff.mul_f64(rand::thread_rng().gen_range(0.0..=0.5))
+    }
+
+    // The max-age of a fetched key set bounded by the cache durations, the minimum taking
+    // precedence so that a zero maximum duration or max-age can't refetch in a hot loop
+    fn refresh_in(&self, max_age: Option<Duration>) -> Duration {
+        max_age
+            .unwrap_or(self.max_duration)
+            .min(self.max_duration)
+            .max(self.min_duration)
+            .max(MIN_REFRESH_INTERVAL)
+    }
+}
+
+impl From<&Settings> for RefreshOptions {
+    fn from(settings: &Settings) -> Self {
+        Self {
+            min_duration: settings.min_duration(),
+            max_duration: settings.duration(),
+            cooldown: settings.refresh_cooldown(),
+            unknown_kid_wait: settings.unknown_kid_wait(),
+            retry_initial: settings.retry_initial(),
+            retry_max: settings.retry_max(),
+            startup: settings.startup,
+            key_file: settings.key_file.clone(),
+            key_file_max_age: settings.key_file_max_age(),
+            allow_empty: settings.allow_empty_key_set,
+            removed_key_grace: settings.removed_key_grace(),
+            algorithms: settings.algorithms.clone(),
+        }
+    }
+}
+
+/// Constructs a `JwkCache` and its `TokenVerifier` from `Settings`, optionally overriding how
+/// the key set is retrieved
+pub struct JwkCacheBuilder {
+    settings: Settings,
+    client: Option<reqwest::Client>,
+    source: Option<Box<dyn KeySource>>,
+    seed: HashMap<String, VerifyingKey>,
+}
+
+impl JwkCacheBuilder {
+    /// Fetch the jwks url with `client` in place of one built from the settings
+    pub fn client(self, client: reqwest::Client) -> Self {
+        Self {
+            client: Some(client),
+            ..self
+        }
+    }
+
+    /// Retrieve keys from `source` in place of the settings url or discovery document; the
+    /// issuers are then never discovered and must be configured
+    pub fn source(self, source: impl KeySource + 'static) -> Self {
+        Self {
+            source: Some(Box::new(source)),
+            ..self
+        }
+    }
+
+    /// Keys to start with under `StartupMode::Lazy` when the initial fetch fails
+    pub fn seed(self, seed: HashMap<String, VerifyingKey>) -> Self {
+        Self { seed, ..self }
+    }
+
+    pub async fn build(self) -> Result<(TokenVerifier, JwkCache), Error> {
+        let settings = self.settings;
+        // Without an allowed issuer any token signed by a key in the set would be accepted; only
+        // the discovery source resolves one in place of the settings
+        let discovery = self.source.is_none() && settings.discovery_url.is_some();
+        if settings.allowed_issuers().is_none() && !discovery {
+            return Err(Error::MissingIssuer);
+        }
+        // Likewise any token minted by the issuer for another client would be accepted
+        if settings.allowed_audiences().is_none() {
+            return Err(Error::MissingAudience);
+        }
+        let (source, issuer) = match self.source {
+            Some(source) => (source, None),
+            None => {
+                let client = match self.client {
+                    Some(client) => client,
+                    None => settings.http_client()?,
+                };
+                match settings.discovery_url {
+                    Some(ref url) => {
+                        let source = DiscoveryKeySource::new(client, url.clone())
+                            .with_recheck(settings.discovery_recheck());
+                        let issuer = source.issuer();
+                        (Box::new(source) as Box<dyn KeySource>, Some(issuer))
+                    }
+                    None => (
+                        source::from_url(
+                            client,
+                            settings.url(),
+                            settings.key_format(),
+                            settings.file_watch(),
+                        ),
+                        None,
+                    ),
+                }
+            }
+        };
+        // Tokens can only be matched to projects by the issuers and audiences derived from them
+        let projects = if settings.issuers.is_none() && settings.audiences.is_none() {
+            settings.app_check_projects()
+        } else {
+            Vec::new()
+        };
+        let (verifier, cache) = JwkCache::init(
+            (&settings).into(),
+            source,
+            settings.verification_options(),
+            settings.app_ids,
+            settings.bearer,
+            self.seed,
+        )
+        .await?;
+        let verifier = match issuer {
+            Some(issuer) => verifier.with_discovered_issuer(issuer),
+            None => verifier,
+        }
+        .with_projects(projects)
+        .with_enforcement_mode(settings.enforcement);
+        Ok((verifier, cache))
+    }
+}
 
 pub struct JwkCache {
-    client: reqwest::Client,
-    duration: Duration,
-    jwks: watch::Sender<HashMap<String, RS256PublicKey>>,
-    url: String,
+    source: Box<dyn KeySource>,
+    options: RefreshOptions,
+    last_refresh: Instant,
+    next_refresh: Instant,
+    consecutive_failures: u32,
+    trigger: RefreshTrigger,
+    jwks: watch::Sender<HashMap<String, VerifyingKey>>,
+    // The current key set as published by the source, for persisting to disk
+    published: Option<jwk_set::JwkSet>,
+    // Keys removed from the key set by a refresh but still accepted until their expiry
+    retired: HashMap<String, (VerifyingKey, Instant)>,
+    status: watch::Sender<KeySetStatus>,
+    rotations: broadcast::Sender<RotationEvent>,
 }
 
 impl JwkCache {
+    /// Fetches the initial key set from `url` and refreshes it according to the Cache-Control
+    /// max-age of each response, never waiting longer than `duration` between fetches. Fails if
+    /// `verify_opts` allows tokens of any issuer or audience.
     pub async fn new(
         duration: Duration,
         url: String,
@@ -26,32 +274,180 @@ impl JwkCache {
         app_ids: Option<HashSet<String>>,
         bearer_settings: Option<BearerSettings>,
     ) -> Result<(TokenVerifier, Self), Error> {
-        let client = reqwest::Client::new();
-        let jwks = jwk_set::fetch_key_set(&client, &url).await?;
-        let (sender, receiver) = watch::channel(jwks);
-        let cache = Self {
-            client,
-            duration,
+        settings::check_verification_options(&verify_opts)?;
+        Self::init(
+            RefreshOptions::with_max_duration(duration),
+            source::from_url(
+                settings::default_http_client()?,
+                &url,
+                KeyFormat::Jwks,
+                None,
+            ),
+            verify_opts,
+            app_ids,
+            bearer_settings,
+            HashMap::new(),
+        )
+        .await
+    }
+
+    pub async fn from_settings(settings: &Settings) -> Result<(TokenVerifier, Self), Error> {
+        Self::builder(settings).build().await
+    }
+
+    /// Like `from_settings`, but with `StartupMode::Lazy` the cache starts with the `seed` keys
+    /// rather than an empty set when the initial fetch fails
+    pub async fn from_settings_with_seed(
+        settings: &Settings,
+        seed: HashMap<String, VerifyingKey>,
+    ) -> Result<(TokenVerifier, Self), Error> {
+        Self::builder(settings).seed(seed).build().await
+    }
+
+    /// Like `from_settings`, but retrieving keys from `source` in place of the configured url
+    pub async fn from_source(
+        settings: &Settings,
+        source: impl KeySource + 'static,
+    ) -> Result<(TokenVerifier, Self), Error> {
+        Self::builder(settings).source(source).build().await
+    }
+
+    pub fn builder(settings: &Settings) -> JwkCacheBuilder {
+        JwkCacheBuilder {
+            settings: settings.clone(),
+            client: None,
+            source: None,
+            seed: HashMap::new(),
+        }
+    }
+
+    async fn init(
+        options: RefreshOptions,
+        source: Box<dyn KeySource>,
+        verify_opts: VerificationOptions,
+        app_ids: Option<HashSet<String>>,
+        bearer_settings: Option<BearerSettings>,
+        seed: HashMap<String, VerifyingKey>,
+    ) -> Result<(TokenVerifier, Self), Error> {
+        let initial =
+            fetch_key_set(source.as_ref(), &options)
+                .await
+                .and_then(|update| match update {
+                    KeySetUpdate::Changed(key_set) => Ok(key_set),
+                    // Nothing has been fetched yet for the source to be unchanged from
+                    KeySetUpdate::Unchanged { .. } => {
+                        Err(Error::JwkSetEmpty(source.name().to_string()))
+                    }
+                });
+        let (key_set, result) = match initial {
+            Ok(key_set) => {
+                let max_age = key_set.max_age;
+                (key_set, Ok(max_age))
+            }
+            Err(err) => match load_key_set(&options).await {
+                Some(key_set) => {
+                    tracing::warn!(?err, "failed to fetch initial jwk set; using stored keys");
+                    (key_set, Err(err))
+                }
+                None if options.startup == StartupMode::Lazy => {
+                    let mut key_set = KeySet::new(seed);
+                    key_set.retain_algorithms(&options.algorithms);
+                    tracing::warn!(
+                        ?err,
+                        seeded_keys = key_set.keys.len(),
+                        "failed to fetch initial jwk set; starting with seeded keys"
+                    );
+                    (key_set, Err(err))
+                }
+                None => return Err(err),
+            },
+        };
+        let KeySet {
+            keys,
+            jwk_set: published,
+            ..
+        } = key_set;
+        tracing::info!(kids = ?keys.keys().collect::<Vec<_>>(), "loaded jwk set");
+        let (sender, receiver) = watch::channel(keys);
+        let (status_sender, status_receiver) = watch::channel(KeySetStatus::default());
+        let trigger = RefreshTrigger::default();
+        let verifier = TokenVerifier::new(receiver, verify_opts, app_ids, bearer_settings)?
+            .with_algorithms(options.algorithms.clone())
+            .with_refresh(trigger.clone(), options.unknown_kid_wait)
+            .with_status(status_receiver);
+        let mut cache = Self {
+            source,
+            options,
+            last_refresh: Instant::now(),
+            next_refresh: Instant::now(),
+            consecutive_failures: 0,
+            trigger,
             jwks: sender,
-            url,
+            published,
+            retired: HashMap::new(),
+            status: status_sender,
+            rotations: broadcast::channel(ROTATION_EVENTS_CAPACITY).0,
         };
-        let verifier = TokenVerifier::new(receiver, verify_opts, app_ids, bearer_settings)?;
+        if result.is_ok() {
+            cache.store_key_set().await;
+        }
+        cache.handle_result(result);
         Ok((verifier, cache))
     }
 
-    pub async fn run(mut self, shutdown: Listener) {
-        tracing::info!("starting firebase appcheck jwk cache");
+    pub fn refresh_trigger(&self) -> RefreshTrigger {
+        self.trigger.clone()
+    }
+
+    /// Subscribes to events for each refresh that adds or removes keys; subscribers that fall
+    /// behind miss the oldest events
+    pub fn subscribe_rotations(&self) -> broadcast::Receiver<RotationEvent> {
+        self.rotations.subscribe()
+    }
+
+    /// Spawns the cache onto the Tokio runtime, running until `shutdown` resolves
+    pub fn spawn(self, shutdown: impl Future<Output = ()> + Send + 'static) -> JwkCacheHandle {
+        let trigger = self.refresh_trigger();
+        JwkCacheHandle::new(tokio::spawn(self.run(shutdown)), trigger)
+    }
 
-        let mut refresh_timer = interval(self.duration);
+    /// Refreshes the key set until `shutdown` resolves; any future will do, such as a
+    /// `triggered::Listener` or `tokio_util::sync::CancellationToken::cancelled_owned()`
+    pub async fn run(mut self, shutdown: impl Future<Output = ()>) {
+        tokio::pin!(shutdown);
+
+        tracing::info!(
+            source = self.source.name(),
+            "starting firebase appcheck jwk cache"
+        );
+
+        let trigger = self.trigger.clone();
 
         loop {
             tokio::select! {
                 biased;
-                _ = shutdown.clone() => break,
-                _ = refresh_timer.tick() => {
-                    if let Err(err) = self.refresh_key_set().await {
-                        tracing::error!(?err, "failure to refresh appcheck verifying public keys");
+                _ = &mut shutdown => break,
+                _ = sleep_until(self.next_refresh) => self.handle_refresh().await,
+                _ = sleep_until(self.next_retired_expiry()), if !self.retired.is_empty() => {
+                    self.expire_retired_keys();
+                }
+                _ = trigger.requested() => {
+                    // Bound how often out-of-band refreshes can hit the jwks endpoint so tokens
+                    // carrying arbitrary kids can't be used to trigger a storm of fetches; only
+                    // forced refreshes, which aren't driven by tokens, bypass the bound. Requests
+                    // within the cooldown are deferred to its end rather than dropped, so a
+                    // newly rotated kid isn't rejected until some later request falls outside it
+                    let forced = trigger.take_forced();
+                    let cooldown_end = self.last_refresh + self.options.cooldown;
+                    if !forced && Instant::now() < cooldown_end {
+                        if cooldown_end < self.next_refresh {
+                            tracing::debug!("deferring jwk set refresh request to end of cooldown");
+                            self.next_refresh = cooldown_end;
+                        }
+                        continue;
                     }
+                    tracing::info!(forced, "refreshing jwk set on demand");
+                    self.handle_refresh().await;
                 }
             }
         }
@@ -59,9 +455,422 @@ impl JwkCache {
         tracing::info!("stopping firebase appcheck jwk cache");
     }
 
-    async fn refresh_key_set(&mut self) -> Result<(), Error> {
-        let new_jwks = jwk_set::fetch_key_set(&self.client, &self.url).await?;
-        self.jwks.send_replace(new_jwks);
-        Ok(())
+    async fn handle_refresh(&mut self) {
+        metrics::counter!("appcheck-jwk-refresh-attempt").increment(1);
+        let result = self.refresh_key_set().await;
+        self.handle_result(result);
+    }
+
+    fn handle_result(&mut self, result: Result<Option<Duration>, Error>) {
+        let failure = match result {
+            Ok(max_age) => {
+                metrics::counter!("appcheck-jwk-refresh", "result" => "success").increment(1);
+                self.consecutive_failures = 0;
+                self.schedule_refresh(max_age);
+                None
+            }
+            Err(err) => {
+                metrics::counter!("appcheck-jwk-refresh", "result" => "failure").increment(1);
+                self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+                tracing::error!(
+                    ?err,
+                    failures = self.consecutive_failures,
+                    "failure to refresh appcheck verifying public keys"
+                );
+                self.schedule_retry();
+                Some(err)
+            }
+        };
+        metrics::gauge!("appcheck-jwk-refresh-consecutive-failures")
+            .set(self.consecutive_failures as f64);
+        self.publish_status(failure);
+    }
+
+    fn publish_status(&self, failure: Option<Error>) {
+        let now = SystemTime::now();
+        let kids: Vec<String> = self.jwks.borrow().keys().cloned().collect();
+        metrics::gauge!("appcheck-jwk-loaded-keys").set(kids.len() as f64);
+        let next_refresh = now + self.next_refresh.saturating_duration_since(Instant::now());
+        self.status.send_modify(|status| {
+            status.kids = kids;
+            status.consecutive_failures = self.consecutive_failures;
+            status.next_refresh = Some(next_refresh);
+            match failure {
+                Some(err) => {
+                    status.last_failure = Some(now);
+                    status.last_error = Some(err.to_string());
+                }
+                None => status.last_refresh = Some(now),
+            }
+        });
+    }
+
+    async fn refresh_key_set(&mut self) -> Result<Option<Duration>, Error> {
+        self.last_refresh = Instant::now();
+        match fetch_key_set(self.source.as_ref(), &self.options).await? {
+            KeySetUpdate::Unchanged { max_age } => {
+                // Re-store the unchanged key set to keep its fetched time current on disk
+                self.store_key_set().await;
+                Ok(max_age)
+            }
+            KeySetUpdate::Changed(key_set) => {
+                let (added, removed) = self.changed_kids(&key_set.keys);
+                if !added.is_empty() || !removed.is_empty() {
+                    self.publish_rotation(added, removed);
+                }
+                self.published = key_set.jwk_set;
+                self.store_key_set().await;
+                let keys = self.retain_removed_keys(key_set.keys);
+                self.jwks.send_replace(keys);
+                Ok(key_set.max_age)
+            }
+        }
+    }
+
+    fn publish_rotation(&self, added: Vec<String>, removed: Vec<String>) {
+        tracing::info!(?added, ?removed, "jwk set changed");
+        metrics::counter!("appcheck-jwk-rotation").increment(1);
+        metrics::counter!("appcheck-jwk-keys-added").increment(added.len() as u64);
+        metrics::counter!("appcheck-jwk-keys-removed").increment(removed.len() as u64);
+        // Sending only fails when there are no subscribers
+        let _ = self.rotations.send(RotationEvent {
+            added,
+            removed,
+            at: SystemTime::now(),
+            source: self.source.name().to_string(),
+        });
+    }
+
+    // The kids added to and removed from the current key set by a refreshed key set; keys
+    // already retired were reported as removed by the refresh that dropped them
+    fn changed_kids(&self, keys: &HashMap<String, VerifyingKey>) -> (Vec<String>, Vec<String>) {
+        let current = self.jwks.borrow();
+        let added = keys
+            .keys()
+            .filter(|kid| !current.contains_key(*kid))
+            .cloned()
+            .collect();
+        let removed = current
+            .keys()
+            .filter(|kid| !keys.contains_key(*kid) && !self.retired.contains_key(*kid))
+            .cloned()
+            .collect();
+        (added, removed)
+    }
+
+    // Failing to persist the key set only costs the ability to bootstrap from it on a later
+    // restart, so errors are logged rather than failing the refresh. Key sets that weren't
+    // parsed from a jwks document have no published form to persist.
+    async fn store_key_set(&self) {
+        if let (Some(path), Some(jwk_set)) = (&self.options.key_file, &self.published) {
+            if let Err(err) = jwk_set::store_key_set(path, jwk_set).await {
+                tracing::warn!(?err, ?path, "failed to store jwk set");
+            }
+        }
+    }
+
+    // Adds keys dropped from the key set by this refresh to the retired keys, and returns the
+    // refreshed keys along with any retired keys still within their grace period
+    fn retain_removed_keys(
+        &mut self,
+        mut keys: HashMap<String, VerifyingKey>,
+    ) -> HashMap<String, VerifyingKey> {
+        let now = Instant::now();
+        if let Some(grace) = self.options.removed_key_grace {
+            for (kid, key) in self.jwks.borrow().iter() {
+                if !keys.contains_key(kid) && !self.retired.contains_key(kid) {
+                    tracing::info!(key_id = %kid, ?grace, "retaining removed public key");
+                    self.retired.insert(kid.clone(), (key.clone(), now + grace));
+                }
+            }
+        }
+        self.retired
+            .retain(|kid, (_, expiry)| *expiry > now && !keys.contains_key(kid));
+        keys.extend(
+            self.retired
+                .iter()
+                .map(|(kid, (key, _))| (kid.clone(), key.clone())),
+        );
+        keys
+    }
+
+    fn next_retired_expiry(&self) -> Instant {
+        self.retired
+            .values()
+            .map(|(_, expiry)| *expiry)
+            .min()
+            .unwrap_or(self.next_refresh)
+    }
+
+    fn expire_retired_keys(&mut self) {
+        let now = Instant::now();
+        let expired: Vec<String> = self
+            .retired
+            .iter()
+            .filter(|(_, (_, expiry))| *expiry <= now)
+            .map(|(kid, _)| kid.clone())
+            .collect();
+        for kid in expired.iter() {
+            tracing::info!(key_id = %kid, "removing retired public key after grace period");
+            self.retired.remove(kid);
+        }
+        self.jwks
+            .send_modify(|keys| keys.retain(|kid, _| !expired.contains(kid)));
+        let kids: Vec<String> = self.jwks.borrow().keys().cloned().collect();
+        metrics::gauge!("appcheck-jwk-loaded-keys").set(kids.len() as f64);
+        self.status.send_modify(|status| status.kids = kids);
+    }
+
+    // Schedules the next fetch after the freshness lifetime reported by the last response,
+    // bounded by the configured min and max cache durations
+    fn schedule_refresh(&mut self, max_age: Option<Duration>) {
+        let refresh_in = self.options.refresh_in(max_age);
+        tracing::debug!(?refresh_in, "scheduling next jwk set refresh");
+        self.next_refresh = Instant::now() + refresh_in;
+    }
+
+    fn schedule_retry(&mut self) {
+        let retry_in = self.options.retry_in(self.consecutive_failures);
+        tracing::info!(?retry_in, "scheduling retry of jwk set refresh");
+        self.next_refresh = Instant::now() + retry_in;
+    }
+}
+
+// Drops keys for algorithms that aren't allowed. An empty key set rejects every token, so unless
+// explicitly allowed it's treated as a failed fetch rather than replacing the current keys, and
+// isn't committed to the source so the next fetch doesn't revalidate it as unchanged.
+async fn fetch_key_set(
+    source: &dyn KeySource,
+    options: &RefreshOptions,
+) -> Result<KeySetUpdate, Error> {
+    let mut update = source.fetch().await?;
+    if let KeySetUpdate::Changed(ref mut key_set) = update {
+        key_set.retain_algorithms(&options.algorithms);
+        if key_set.keys.is_empty() && !options.allow_empty {
+            return Err(Error::JwkSetEmpty(source.name().to_string()));
+        }
+    }
+    source.commit();
+    Ok(update)
+}
+
+// Loads the stored key set held to the same algorithms and emptiness checks as a fetched one,
+// since it may have been written under different settings
+async fn load_key_set(options: &RefreshOptions) -> Option<KeySet> {
+    let path = options.key_file.as_ref()?;
+    let mut key_set = jwk_set::load_key_set(path, options.key_file_max_age)
+        .await
+        .map_err(|err| tracing::warn!(?err, ?path, "unable to load stored jwk set"))
+        .ok()?;
+    key_set.retain_algorithms(&options.algorithms);
+    if key_set.keys.is_empty() && !options.allow_empty {
+        tracing::warn!(?path, "stored jwk set has no usable keys");
+        return None;
+    }
+    Some(key_set)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::future::BoxFuture;
+    use tokio::{sync::mpsc, time::timeout};
+
+    // Reports each fetch of an empty key set
+    struct RecordingSource(mpsc::UnboundedSender<()>);
+
+    impl KeySource for RecordingSource {
+        fn fetch(&self) -> BoxFuture<'_, Result<KeySetUpdate, Error>> {
+            let _ = self.0.send(());
+            Box::pin(async { Ok(KeySetUpdate::Changed(KeySet::new(HashMap::new()))) })
+        }
+
+        fn name(&self) -> &str {
+            "recording"
+        }
+    }
+
+    async fn spawn_recording_cache(
+        cooldown_secs: u64,
+    ) -> (JwkCacheHandle, mpsc::UnboundedReceiver<()>) {
+        let settings: Settings = serde_json::from_value(serde_json::json!({
+            "project_num": 1,
+            "allow_empty_key_set": true,
+            "refresh_cooldown_secs": cooldown_secs,
+        }))
+        .unwrap();
+        let (sender, mut fetches) = mpsc::unbounded_channel();
+        let (_, cache) = JwkCache::builder(&settings)
+            .source(RecordingSource(sender))
+            .build()
+            .await
+            .unwrap();
+        fetches.recv().await.unwrap();
+        (cache.spawn(std::future::pending()), fetches)
+    }
+
+    #[tokio::test]
+    async fn forced_refresh_bypasses_cooldown() {
+        let (handle, mut fetches) = spawn_recording_cache(30).await;
+
+        handle.refresh();
+        assert!(timeout(Duration::from_millis(100), fetches.recv())
+            .await
+            .is_err());
+        handle.force_refresh();
+        assert!(timeout(Duration::from_secs(1), fetches.recv())
+            .await
+            .is_ok());
+        handle.into_join_handle().abort();
+    }
+
+    #[tokio::test]
+    async fn stored_keys_for_disallowed_algorithms_are_not_loaded() {
+        let path = std::env::temp_dir().join(format!("appcheck-keys-{}.json", std::process::id()));
+        let stored = serde_json::json!({
+            "fetched_at": SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+            "keys": [{
+                "kty": "RSA",
+                "alg": "RS256",
+                "kid": "rsa",
+                "n": "vaAj7nTPT4t-xL7ILzCwy1UW1PKEV9oD7lOfkkEA-7nFxMbBvSYCvAzbZPat1O0xntAZqTvvFDfgjVFVBTTUDS0Zz7WHxoviykeFTJLlurhP0rz7QuejAmZUgX6CxTu7zXURnfFZ0-QF5uFWpOm-ogGONPu7qhRDlwQ38FEfbHqY3QkOKK8TSg_2t86xKfGMyiaEUgfQyQUex2Vurgbn9f9JzXivpokaJFB8YTRe_P0cD0BiS2_PMvIkzLlP5njoumwh_MhBGYa8oO2kI6D6za3rZIcbHeB2eCWWI3DS3KyjVo8nA6R9oVYZ0N7nUVuP_c3vk8K_QFg_E2uPml7RdQ",
+                "e": "AQAB",
+            }],
+        });
+        tokio::fs::write(&path, stored.to_string()).await.unwrap();
+        let settings = |algorithms: &[&str]| -> Settings {
+            serde_json::from_value(serde_json::json!({
+                "project_num": 1,
+                "key_file": path,
+                "algorithms": algorithms,
+            }))
+            .unwrap()
+        };
+        // The source has no keys, so the cache has to start from the stored ones
+        let build = |settings: Settings| async move {
+            JwkCache::builder(&settings)
+                .source(StaticKeySource::new(HashMap::new()))
+                .build()
+                .await
+        };
+
+        let (verifier, _) = build(settings(&["RS256"])).await.unwrap();
+        assert!(verifier.is_ready());
+        assert!(matches!(
+            build(settings(&["ES256"])).await,
+            Err(Error::JwkSetEmpty(_))
+        ));
+        let _ = tokio::fs::remove_file(&path).await;
+    }
+
+    #[tokio::test]
+    async fn custom_source_requires_configured_issuers_despite_discovery_url() {
+        let settings: Settings = serde_json::from_value(serde_json::json!({
+            "discovery_url": "https://issuer.example.com/.well-known/openid-configuration",
+            "audiences": ["client"],
+        }))
+        .unwrap();
+        let result = JwkCache::builder(&settings)
+            .source(StaticKeySource::new(HashMap::new()))
+            .build()
+            .await;
+        assert!(matches!(result, Err(Error::MissingIssuer)));
+    }
+
+    #[tokio::test]
+    async fn refresh_within_cooldown_is_deferred() {
+        let (handle, mut fetches) = spawn_recording_cache(1).await;
+
+        handle.refresh();
+        handle.refresh();
+        assert!(timeout(Duration::from_millis(100), fetches.recv())
+            .await
+            .is_err());
+        assert!(timeout(Duration::from_secs(2), fetches.recv())
+            .await
+            .is_ok());
+        assert!(timeout(Duration::from_millis(100), fetches.recv())
+            .await
+            .is_err());
+        handle.into_join_handle().abort();
+    }
+
+    #[tokio::test]
+    async fn rejected_key_set_is_fetched_again() {
+        let path = std::env::temp_dir().join(format!("appcheck-empty-{}.json", std::process::id()));
+        tokio::fs::write(&path, br#"{"keys": []}"#).await.unwrap();
+        let source = FileKeySource::new(&path);
+        let options = RefreshOptions::with_max_duration(Duration::from_secs(3600));
+
+        for _ in 0..2 {
+            let result = fetch_key_set(&source, &options).await;
+            assert!(matches!(result, Err(Error::JwkSetEmpty(_))));
+        }
+        let _ = tokio::fs::remove_file(&path).await;
+    }
+
+    #[test]
+    fn refresh_interval_is_bounded_by_cache_durations() {
+        let options = RefreshOptions {
+            min_duration: Duration::from_secs(300),
+            max_duration: Duration::from_secs(3600),
+            ..RefreshOptions::with_max_duration(Duration::from_secs(3600))
+        };
+        assert_eq!(options.refresh_in(None), Duration::from_secs(3600));
+        assert_eq!(
+            options.refresh_in(Some(Duration::from_secs(600))),
+            Duration::from_secs(600)
+        );
+        assert_eq!(
+            options.refresh_in(Some(Duration::ZERO)),
+            Duration::from_secs(300)
+        );
+        assert_eq!(
+            options.refresh_in(Some(Duration::from_secs(86400))),
+            Duration::from_secs(3600)
+        );
+    }
+
+    #[test]
+    fn zero_duration_refreshes_at_minimum_interval() {
+        let options = RefreshOptions {
+            max_duration: Duration::ZERO,
+            ..RefreshOptions::with_max_duration(Duration::from_secs(3600))
+        };
+        assert_eq!(options.refresh_in(None), Duration::from_secs(300));
+
+        let options = RefreshOptions::with_max_duration(Duration::ZERO);
+        assert_eq!(options.refresh_in(None), DEFAULT_MIN_DURATION);
+
+        let options = RefreshOptions {
+            min_duration: Duration::ZERO,
+            ..options
+        };
+        assert_eq!(
+            options.refresh_in(Some(Duration::ZERO)),
+            MIN_REFRESH_INTERVAL
+        );
+    }
+
+    #[test]
+    fn retries_back_off_exponentially_with_jitter() {
+        let options = RefreshOptions {
+            retry_initial: Duration::from_secs(5),
+            retry_max: Duration::from_secs(300),
+            ..RefreshOptions::with_max_duration(Duration::from_secs(3600))
+        };
+        for (failures, backoff) in [(1, 5), (2, 10), (3, 20), (4, 40), (7, 300), (u32::MAX, 300)] {
+            let backoff = Duration::from_secs(backoff);
+            for _ in 0..32 {
+                let retry_in = options.retry_in(failures);
+                assert!(
+                    retry_in >= backoff / 2 && retry_in <= backoff,
+                    "{retry_in:?} outside jitter of {backoff:?} after {failures} failures"
+                );
+            }
+        }
     }
 }
@@ -0,0 +1,14 @@
+use std::time::SystemTime;
+
+/// Emitted by `JwkCache` whenever a refresh changes the kids in the key set
+#[derive(Clone, Debug)]
+pub struct RotationEvent {
+    /// The kids of keys added by the refresh
+    pub added: Vec<String>,
+    /// The kids of keys removed by the refresh; these may still be accepted for a grace period
+    pub removed: Vec<String>,
+    /// When the rotation was observed
+    pub at: SystemTime,
+    /// The name of the key source, e.g. the jwks url
+    pub source: String,
+}
@@ -0,0 +1,195 @@
+use super::{
+    jwk_set::{self, KeyFormat, KeySetUpdate, Validators},
+    Duration, Error, HashMap, KeySet, VerifyingKey,
+};
+use futures_util::future::BoxFuture;
+use std::{path::PathBuf, sync::Mutex, time::SystemTime};
+
+/// A source of the public keys held and periodically refreshed by `JwkCache`
+pub trait KeySource: Send + Sync {
+    /// Retrieves the current key set from the source, or reports that it's unchanged since
+    /// the previous fetch
+    fn fetch(&self) -> BoxFuture<'_, Result<KeySetUpdate, Error>>;
+
+    /// Identifies the source in logs
+    fn name(&self) -> &str;
+
+    /// Called once the cache has accepted the key set of the last fetch, e.g. to remember what
+    /// to revalidate it with; key sets the cache rejects are fetched again in full
+    fn commit(&self) {}
+}
+
+/// Fetches keys from a jwks or x509 certificate endpoint over HTTP, honoring the Cache-Control max-age of responses
+/// and revalidating with the ETag and Last-Modified of the previous response
+pub struct HttpKeySource {
+    client: reqwest::Client,
+    url: String,
+    format: KeyFormat,
+    validators: Mutex<Validators>,
+    // The validators of the last fetch until the cache accepts its key set
+    fetched: Mutex<Option<Validators>>,
+}
+
+impl HttpKeySource {
+    pub fn new(client: reqwest::Client, url: String) -> Self {
+        Self {
+            client,
+            url,
+            format: KeyFormat::default(),
+            validators: Mutex::default(),
+            fetched: Mutex::default(),
+        }
+    }
+
+    /// Parse responses as `format` in place of a jwks document
+    pub fn with_format(self, format: KeyFormat) -> Self {
+        Self { format, ..self }
+    }
+}
+
+impl KeySource for HttpKeySource {
+    fn fetch(&self) -> BoxFuture<'_, Result<KeySetUpdate, Error>> {
+        Box::pin(async move {
+            let validators = self
+                .validators
+                .lock()
+                .map(|validators| validators.clone())
+                .unwrap_or_default();
+            let (update, validators) =
+                jwk_set::fetch_key_set(&self.client, &self.url, self.format, validators).await?;
+            if let Ok(mut fetched) = self.fetched.lock() {
+                *fetched = Some(validators);
+            }
+            Ok(update)
+        })
+    }
+
+    fn name(&self) -> &str {
+        &self.url
+    }
+
+    fn commit(&self) {
+        let fetched = self
+            .fetched
+            .lock()
+            .ok()
+            .and_then(|mut fetched| fetched.take());
+        if let (Some(fetched), Ok(mut validators)) = (fetched, self.validators.lock()) {
+            *validators = fetched;
+        }
+    }
+}
+
+/// Reads keys from a jwks or x509 certificate document on the local filesystem. When watched the file is checked
+/// every `watch` interval (bounded by the cache's minimum duration) and re-read if modified.
+pub struct FileKeySource {
+    path: PathBuf,
+    name: String,
+    format: KeyFormat,
+    watch: Option<Duration>,
+    modified: Mutex<Option<SystemTime>>,
+    // The modification time of the last read until the cache accepts its key set
+    read: Mutex<Option<Option<SystemTime>>>,
+}
+
+impl FileKeySource {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        Self {
+            name: path.display().to_string(),
+            path,
+            format: KeyFormat::default(),
+            watch: None,
+            modified: Mutex::default(),
+            read: Mutex::default(),
+        }
+    }
+
+    /// Parse the file as `format` in place of a jwks document
+    pub fn with_format(self, format: KeyFormat) -> Self {
+        Self { format, ..self }
+    }
+
+    pub fn with_watch(self, watch: Duration) -> Self {
+        Self {
+            watch: Some(watch),
+            ..self
+        }
+    }
+}
+
+impl KeySource for FileKeySource {
+    fn fetch(&self) -> BoxFuture<'_, Result<KeySetUpdate, Error>> {
+        Box::pin(async move {
+            let modified = tokio::fs::metadata(&self.path).await?.modified().ok();
+            let unchanged = self
+                .modified
+                .lock()
+                .is_ok_and(|last_modified| modified.is_some() && *last_modified == modified);
+            if unchanged {
+                return Ok(KeySetUpdate::Unchanged {
+                    max_age: self.watch,
+                });
+            }
+
+            let jwks = tokio::fs::read(&self.path).await?;
+            let key_set = KeySet::parse(&jwks, self.format)?.with_max_age(self.watch);
+            if let Ok(mut read) = self.read.lock() {
+                *read = Some(modified);
+            }
+            Ok(KeySetUpdate::Changed(key_set))
+        })
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn commit(&self) {
+        let read = self.read.lock().ok().and_then(|mut read| read.take());
+        if let (Some(read), Ok(mut last_modified)) = (read, self.modified.lock()) {
+            *last_modified = read;
+        }
+    }
+}
+
+/// Serves a fixed set of keys supplied programmatically, e.g. for tests or offline environments
+pub struct StaticKeySource {
+    keys: HashMap<String, VerifyingKey>,
+}
+
+impl StaticKeySource {
+    pub fn new(keys: HashMap<String, VerifyingKey>) -> Self {
+        Self { keys }
+    }
+}
+
+impl KeySource for StaticKeySource {
+    fn fetch(&self) -> BoxFuture<'_, Result<KeySetUpdate, Error>> {
+        Box::pin(async move { Ok(KeySetUpdate::Changed(KeySet::new(self.keys.clone()))) })
+    }
+
+    fn name(&self) -> &str {
+        "static"
+    }
+}
+
+/// Selects the source for a configured jwks url; `file://` urls are read from the local
+/// filesystem and anything else is fetched over HTTP
+pub(super) fn from_url(
+    client: reqwest::Client,
+    url: &str,
+    format: KeyFormat,
+    watch: Option<Duration>,
+) -> Box<dyn KeySource> {
+    match url.strip_prefix("file://") {
+        Some(path) => {
+            let source = FileKeySource::new(path).with_format(format);
+            Box::new(match watch {
+                Some(watch) => source.with_watch(watch),
+                None => source,
+            })
+        }
+        None => Box::new(HttpKeySource::new(client, url.to_string()).with_format(format)),
+    }
+}
@@ -0,0 +1,42 @@
+use serde::{Serialize, Serializer};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Snapshot of the health of the key set held by a `JwkCache`, published after every
+/// refresh attempt
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct KeySetStatus {
+    /// The kids of the currently loaded keys
+    pub kids: Vec<String>,
+    /// When the key set was last successfully refreshed, if ever
+    #[serde(serialize_with = "unix_secs")]
+    pub last_refresh: Option<SystemTime>,
+    /// When a refresh of the key set last failed, if ever
+    #[serde(serialize_with = "unix_secs")]
+    pub last_failure: Option<SystemTime>,
+    /// The error of the most recent failed refresh
+    pub last_error: Option<String>,
+    /// The number of refresh failures since the last successful refresh
+    pub consecutive_failures: u32,
+    /// When the next refresh is scheduled
+    #[serde(serialize_with = "unix_secs")]
+    pub next_refresh: Option<SystemTime>,
+}
+
+impl KeySetStatus {
+    /// Whether the key set hasn't been successfully refreshed within `max_staleness`; a key set
+    /// that was only ever seeded or loaded from disk is always stale
+    pub fn is_stale(&self, max_staleness: Duration) -> bool {
+        self.last_refresh
+            .and_then(|last_refresh| last_refresh.elapsed().ok())
+            .is_none_or(|elapsed| elapsed > max_staleness)
+    }
+}
+
+fn unix_secs<S>(time: &Option<SystemTime>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    time.and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|since_epoch| since_epoch.as_secs())
+        .serialize(serializer)
+}
@@ -0,0 +1,135 @@
+use jwt_simple::{
+    algorithms::{
+        ECDSAP256PublicKeyLike, ES256PublicKey, Ed25519PublicKey, EdDSAPublicKeyLike,
+        PS256PublicKey, RS256PublicKey, RS384PublicKey, RS512PublicKey, RSAPublicKeyLike,
+    },
+    claims::JWTClaims,
+    common::VerificationOptions,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::fmt;
+
+/// JWS signature algorithms supported for verifying tokens against cached keys
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq, Hash)]
+pub enum Algorithm {
+    RS256,
+    RS384,
+    RS512,
+    PS256,
+    ES256,
+    EdDSA,
+}
+
+impl Algorithm {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::RS256 => "RS256",
+            Self::RS384 => "RS384",
+            Self::RS512 => "RS512",
+            Self::PS256 => "PS256",
+            Self::ES256 => "ES256",
+            Self::EdDSA => "EdDSA",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "RS256" => Some(Self::RS256),
+            "RS384" => Some(Self::RS384),
+            "RS512" => Some(Self::RS512),
+            "PS256" => Some(Self::PS256),
+            "ES256" => Some(Self::ES256),
+            "EdDSA" => Some(Self::EdDSA),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A public key for verifying token signatures of a single algorithm
+#[derive(Clone)]
+pub enum VerifyingKey {
+    RS256(RS256PublicKey),
+    RS384(RS384PublicKey),
+    RS512(RS512PublicKey),
+    PS256(PS256PublicKey),
+    ES256(ES256PublicKey),
+    EdDSA(Ed25519PublicKey),
+}
+
+impl VerifyingKey {
+    pub fn algorithm(&self) -> Algorithm {
+        match self {
+            Self::RS256(_) => Algorithm::RS256,
+            Self::RS384(_) => Algorithm::RS384,
+            Self::RS512(_) => Algorithm::RS512,
+            Self::PS256(_) => Algorithm::PS256,
+            Self::ES256(_) => Algorithm::ES256,
+            Self::EdDSA(_) => Algorithm::EdDSA,
+        }
+    }
+
+    pub fn verify_token<CustomClaims: Serialize + DeserializeOwned>(
+        &self,
+        token: &str,
+        options: Option<VerificationOptions>,
+    ) -> Result<JWTClaims<CustomClaims>, jwt_simple::Error> {
+        match self {
+            Self::RS256(key) => key.verify_token(token, options),
+            Self::RS384(key) => key.verify_token(token, options),
+            Self::RS512(key) => key.verify_token(token, options),
+            Self::PS256(key) => key.verify_token(token, options),
+            Self::ES256(key) => key.verify_token(token, options),
+            Self::EdDSA(key) => key.verify_token(token, options),
+        }
+    }
+}
+
+impl fmt::Debug for VerifyingKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("VerifyingKey")
+            .field(&self.algorithm())
+            .finish()
+    }
+}
+
+impl From<RS256PublicKey> for VerifyingKey {
+    fn from(key: RS256PublicKey) -> Self {
+        Self::RS256(key)
+    }
+}
+
+impl From<RS384PublicKey> for VerifyingKey {
+    fn from(key: RS384PublicKey) -> Self {
+        Self::RS384(key)
+    }
+}
+
+impl From<RS512PublicKey> for VerifyingKey {
+    fn from(key: RS512PublicKey) -> Self {
+        Self::RS512(key)
+    }
+}
+
+impl From<PS256PublicKey> for VerifyingKey {
+    fn from(key: PS256PublicKey) -> Self {
+        Self::PS256(key)
+    }
+}
+
+impl From<ES256PublicKey> for VerifyingKey {
+    fn from(key: ES256PublicKey) -> Self {
+        Self::ES256(key)
+    }
+}
+
+impl From<Ed25519PublicKey> for VerifyingKey {
+    fn from(key: Ed25519PublicKey) -> Self {
+        Self::EdDSA(key)
+    }
+}
@@ -0,0 +1,185 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+const SEQUENCE: u8 = 0x30;
+const INTEGER: u8 = 0x02;
+const EXPLICIT_VERSION: u8 = 0xa0;
+
+/// Extracts the DER encoded SubjectPublicKeyInfo from a PEM encoded x509 certificate, as
+/// published for the keys signing Firebase Authentication ID tokens
+pub(super) fn public_key_der(pem: &str) -> Option<Vec<u8>> {
+    let der = pem_contents(pem)?;
+
+    // Certificate ::= SEQUENCE { tbsCertificate, signatureAlgorithm, signatureValue }
+    let (certificate, _) = read_element(&der, SEQUENCE)?;
+    let (tbs_certificate, _) = read_element(certificate.contents, SEQUENCE)?;
+
+    // TBSCertificate ::= SEQUENCE { [0] version OPTIONAL, serialNumber, signature, issuer,
+    //     validity, subject, subjectPublicKeyInfo, ... }
+    let mut rest = tbs_certificate.contents;
+    if rest.first() == Some(&EXPLICIT_VERSION) {
+        rest = read_element(rest, EXPLICIT_VERSION)?.1;
+    }
+    rest = read_element(rest, INTEGER)?.1;
+    for _ in 0..4 {
+        rest = read_element(rest, SEQUENCE)?.1;
+    }
+    let (public_key_info, _) = read_element(rest, SEQUENCE)?;
+    Some(public_key_info.encoded.to_vec())
+}
+
+fn pem_contents(pem: &str) -> Option<Vec<u8>> {
+    let body: String = pem
+        .lines()
+        .map(str::trim)
+        .skip_while(|line| !line.starts_with("-----BEGIN CERTIFICATE-----"))
+        .skip(1)
+        .take_while(|line| !line.starts_with("-----END CERTIFICATE-----"))
+        .collect();
+    STANDARD.decode(body).ok()
+}
+
+struct Element<'a> {
+    // The whole element including its tag and length
+    encoded: &'a [u8],
+    contents: &'a [u8],
+}
+
+// Reads the DER element of the expected tag at the start of `der`, returning it along with the
+// remaining bytes
+fn read_element(der: &[u8], tag: u8) -> Option<(Element<'_>, &[u8])> {
+    let (&actual, rest) = der.split_first()?;
+    if actual != tag {
+        return None;
+    }
+    let (&first, rest) = rest.split_first()?;
+    let (len, rest) = if first < 0x80 {
+        (first as usize, rest)
+    } else {
+        let len_bytes = (first & 0x7f) as usize;
+        if len_bytes == 0 || len_bytes > 4 || rest.len() < len_bytes {
+            return None;
+        }
+        let (len, rest) = rest.split_at(len_bytes);
+        let len = len
+            .iter()
+            .fold(0usize, |len, byte| len << 8 | *byte as usize);
+        (len, rest)
+    };
+    if rest.len() < len {
+        return None;
+    }
+    let (contents, rest) = rest.split_at(len);
+    let header_len = der.len() - rest.len() - len;
+    Some((
+        Element {
+            encoded: &der[..header_len + len],
+            contents,
+        },
+        rest,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jwt_simple::algorithms::RS256PublicKey;
+
+    // A self-signed RSA 2048 certificate in the shape of those published for the securetoken
+    // service account, along with its public key as extracted by `openssl x509 -pubkey`
+    const SECURETOKEN_CERT: &str = "\
+-----BEGIN CERTIFICATE-----\n\
+MIIDQzCCAiugAwIBAgIUJFasV2dHLGO1P+DOxMCc/bze97EwDQYJKoZIhvcNAQEL\n\
+BQAwMTEvMC0GA1UEAwwmc2VjdXJldG9rZW4uc3lzdGVtLmdzZXJ2aWNlYWNjb3Vu\n\
+dC5jb20wHhcNMjYxMDE3MDQxMjEzWhcNMjYxMDMxMDQxMjEzWjAxMS8wLQYDVQQD\n\
+DCZzZWN1cmV0b2tlbi5zeXN0ZW0uZ3NlcnZpY2VhY2NvdW50LmNvbTCCASIwDQYJ\n\
+KoZIhvcNAQEBBQADggEPADCCAQoCggEBAL2gI+50z0+LfsS+yC8wsMtVFtTyhFfa\n\
+A+5Tn5JBAPu5xcTGwb0mArwM22T2rdTtMZ7QGak77xQ34I1RVQU01A0tGc+1h8aL\n\
+4spHhUyS5bq4T9K8+0LnowJmVIF+gsU7u811EZ3xWdPkBebhVqTpvqIBjjT7u6oU\n\
+Q5cEN/BRH2x6mN0JDiivE0oP9rfOsSnxjMomhFIH0MkFHsdlbq4G5/X/Sc14r6aJ\n\
+GiRQfGE0Xvz9HA9AYktvzzLyJMy5T+Z46LpsIfzIQRmGvKDtpCOg+s2t62SHGx3g\n\
+dnglliNw0tyso1aPJwOkfaFWGdDe51Fbj/3N75PCv0BYPxNrj5pe0XUCAwEAAaNT\n\
+MFEwHQYDVR0OBBYEFPFsSHu6eR+nE/402xh2U0PbIMcUMB8GA1UdIwQYMBaAFPFs\n\
+SHu6eR+nE/402xh2U0PbIMcUMA8GA1UdEwEB/wQFMAMBAf8wDQYJKoZIhvcNAQEL\n\
+BQADggEBAJJUU8uiUm/xcoXqW/Jqh8zI8EBWBB9dKuia4kp2Wersb9tx1MvfM1yi\n\
+PRdK9Jr6nj2oDcSSz+UAKK4jWmmzPHBcWdsRZr4tO6lFYv6cdu/q5+V8CqrJyeUV\n\
+MxpUKDZw9aG52qp2+JlvhH36sizk2/hsBOQhrVRRNZ4C/8T2TecYAXIgsj/fOhUu\n\
+Qbwv4550Ji08qANZg431spsBz0Rcq+wZyOl+fnrDiualO57eV8qcyhXDq51RH5ie\n\
+UXDZUTHdvy530Rp0GD+VtOzeAxJlY0JkusdOvGEwMB+jmPkCsKiXyRN7GYqATjau\n\
+GX0LJqZcMp49LjNIfTBlT4o9Yw1kFAU=\n\
+-----END CERTIFICATE-----\n\
+";
+    const SECURETOKEN_PUBLIC_KEY: &str = "\
+MIIBIjANBgkqhkiG9w0BAQEFAAOCAQ8AMIIBCgKCAQEAvaAj7nTPT4t+xL7ILzCw
+y1UW1PKEV9oD7lOfkkEA+7nFxMbBvSYCvAzbZPat1O0xntAZqTvvFDfgjVFVBTTU
+DS0Zz7WHxoviykeFTJLlurhP0rz7QuejAmZUgX6CxTu7zXURnfFZ0+QF5uFWpOm+
+ogGONPu7qhRDlwQ38FEfbHqY3QkOKK8TSg/2t86xKfGMyiaEUgfQyQUex2Vurgbn
+9f9JzXivpokaJFB8YTRe/P0cD0BiS2/PMvIkzLlP5njoumwh/MhBGYa8oO2kI6D6
+za3rZIcbHeB2eCWWI3DS3KyjVo8nA6R9oVYZ0N7nUVuP/c3vk8K/QFg/E2uPml7R
+dQIDAQAB";
+
+    fn certificate_der() -> Vec<u8> {
+        pem_contents(SECURETOKEN_CERT).unwrap()
+    }
+
+    fn pem(der: &[u8]) -> String {
+        format!(
+            "-----BEGIN CERTIFICATE-----\n{}\n-----END CERTIFICATE-----\n",
+            STANDARD.encode(der)
+        )
+    }
+
+    #[test]
+    fn extracts_subject_public_key_info() {
+        let public_key = public_key_der(SECURETOKEN_CERT).unwrap();
+        assert_eq!(
+            public_key,
+            STANDARD
+                .decode(SECURETOKEN_PUBLIC_KEY.replace('\n', ""))
+                .unwrap()
+        );
+        assert!(RS256PublicKey::from_der(&public_key).is_ok());
+    }
+
+    #[test]
+    fn accepts_crlf_line_endings() {
+        assert!(public_key_der(&SECURETOKEN_CERT.replace('\n', "\r\n")).is_some());
+    }
+
+    #[test]
+    fn rejects_truncated_certificates() {
+        let der = certificate_der();
+        for len in 0..der.len() {
+            assert_eq!(
+                public_key_der(&pem(&der[..len])),
+                None,
+                "truncated to {len}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_certificates() {
+        assert_eq!(public_key_der(""), None);
+        assert_eq!(public_key_der("not a certificate"), None);
+        assert_eq!(
+            public_key_der("-----BEGIN CERTIFICATE-----\n!!!\n-----END CERTIFICATE-----"),
+            None
+        );
+
+        let der = certificate_der();
+        let malformed = |offset: usize, byte: u8| {
+            let mut der = der.clone();
+            der[offset] = byte;
+            public_key_der(&pem(&der))
+        };
+        // Certificate tag other than SEQUENCE
+        assert_eq!(malformed(0, 0x31), None);
+        // Indefinite and oversized length encodings
+        assert_eq!(malformed(1, 0x80), None);
+        assert_eq!(malformed(1, 0x85), None);
+        // TBSCertificate length overrunning the certificate
+        assert_eq!(malformed(6, 0x04), None);
+        // Serial number tag other than INTEGER
+        assert_eq!(malformed(13, 0x04), None);
+    }
+}
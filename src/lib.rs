@@ -1,14 +1,33 @@
+pub mod admin;
+pub mod app_check_claims;
 pub mod bearer;
+pub mod consumer;
+pub mod id_token;
 pub mod jwk_cache;
 pub mod middleware;
+pub mod policy;
+pub mod rejection;
+pub mod replay;
 mod settings;
 pub mod token_verifier;
 
-pub use jwk_cache::JwkCache;
-pub use settings::{BearerSettings, Settings};
-pub use token_verifier::TokenVerifier;
+pub use app_check_claims::{AppCheckClaims, MissingAppCheckClaims};
+pub use consumer::{ServiceAccountKey, TokenConsumer, TokenConsumption};
+pub use id_token::IdTokenClaims;
+pub use jwk_cache::{
+    Algorithm, JwkCache, JwkCacheBuilder, JwkCacheHandle, KeyFormat, KeySet, KeySetStatus,
+    KeySetUpdate, KeySource, RefreshTrigger, RotationEvent,
+};
+pub use middleware::{AppCheckVerdict, Enforcement};
+pub use policy::{Policy, RoutePolicy};
+pub use rejection::{Rejection, RejectionHandler};
+pub use replay::{MemoryReplayStore, ReplayStore};
+pub use settings::{BearerSettings, EnforcementMode, ProjectSettings, Settings, StartupMode};
+pub use token_verifier::{AppCheckProject, TokenVerifier};
 
 pub use jwt_simple::claims;
+#[cfg(feature = "triggered")]
+pub use triggered;
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -20,6 +39,28 @@ pub enum Error {
     JwkRefresh(#[from] reqwest::Error),
     #[error("no compatible keys in set {0}")]
     JwkSetEmpty(String),
+    #[error("failed to read or write jwk set file {0}")]
+    JwkFile(#[from] std::io::Error),
+    #[error("failed to parse or serialize jwk set {0}")]
+    JwkSerde(#[from] serde_json::Error),
+    #[error("jwk set file expired; fetched {0} secs ago")]
+    JwkFileExpired(u64),
+    #[error("invalid or unsupported jwk {0}")]
+    InvalidJwk(String),
+    #[error("token algorithm not allowed {0}")]
+    DisallowedAlgorithm(String),
     #[error("token kid does not match known key {0}")]
     UnknownJwk(String),
+    #[error("no token issuers configured; set issuers, project_num or project_id")]
+    MissingIssuer,
+    #[error("no token audiences configured; set audiences, project_num or project_id")]
+    MissingAudience,
+    #[error("id token auth_time invalid or in the future {0}")]
+    InvalidAuthTime(u64),
+    #[error("failed to record token for replay protection {0}")]
+    ReplayStore(String),
+    #[error("invalid service account credentials {0}")]
+    InvalidCredentials(String),
+    #[error("failed to consume app check token {0}")]
+    ConsumeToken(String),
 }
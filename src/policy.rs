@@ -0,0 +1,238 @@
+use axum::http::Method;
+use std::collections::HashSet;
+
+/// What `AppCheckLayer` requires of requests to a route
+#[derive(Clone, Debug)]
+pub struct RoutePolicy {
+    exempt: bool,
+    bearer_bypass: bool,
+    app_ids: Option<HashSet<String>>,
+    limited_use: bool,
+}
+
+impl Default for RoutePolicy {
+    fn default() -> Self {
+        Self::require()
+    }
+}
+
+impl RoutePolicy {
+    /// Require an App Check token, or a bearer token where the verifier allows them
+    pub fn require() -> Self {
+        Self {
+            exempt: false,
+            bearer_bypass: true,
+            app_ids: None,
+            limited_use: false,
+        }
+    }
+
+    /// Skip the checks entirely, e.g. for health checks or CORS preflight requests
+    pub fn exempt() -> Self {
+        Self {
+            exempt: true,
+            ..Self::require()
+        }
+    }
+
+    /// Don't accept bearer tokens in place of an App Check token
+    pub fn without_bearer_bypass(self) -> Self {
+        Self {
+            bearer_bypass: false,
+            ..self
+        }
+    }
+
+    /// Only accept tokens for `app_ids`, in addition to the allowlist of the verifier
+    pub fn with_app_ids(self, app_ids: HashSet<String>) -> Self {
+        Self {
+            app_ids: Some(app_ids),
+            ..self
+        }
+    }
+
+    /// Accept each token only once, checked against the replay store of the layer and the
+    /// consumer of the verifier; requests are rejected if neither is configured
+    pub fn limited_use(self) -> Self {
+        Self {
+            limited_use: true,
+            ..self
+        }
+    }
+
+    pub fn is_exempt(&self) -> bool {
+        self.exempt
+    }
+
+    pub fn allows_bearer_bypass(&self) -> bool {
+        self.bearer_bypass
+    }
+
+    pub fn app_ids(&self) -> Option<&HashSet<String>> {
+        self.app_ids.as_ref()
+    }
+
+    pub fn requires_limited_use(&self) -> bool {
+        self.limited_use
+    }
+}
+
+/// Selects the `RoutePolicy` for a request from rules matched in order by HTTP method and path,
+/// falling back to requiring App Check.
+///
+/// Path patterns are matched segment by segment: `*` or an Axum style `{param}` matches any one
+/// segment and a trailing `**` matches any remaining segments. Paths are matched as seen by the
+/// layer, so without the prefix of a router it's nested under.
+#[derive(Clone, Debug, Default)]
+pub struct Policy {
+    rules: Vec<Rule>,
+    fallback: RoutePolicy,
+}
+
+#[derive(Clone, Debug)]
+struct Rule {
+    methods: Option<HashSet<Method>>,
+    path: Vec<String>,
+    policy: RoutePolicy,
+}
+
+impl Policy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Apply `policy` to requests of any method matching `path`
+    pub fn route(self, path: &str, policy: RoutePolicy) -> Self {
+        self.rule(None, path, policy)
+    }
+
+    /// Apply `policy` to requests of one of `methods` matching `path`
+    pub fn route_methods(
+        self,
+        methods: impl IntoIterator<Item = Method>,
+        path: &str,
+        policy: RoutePolicy,
+    ) -> Self {
+        self.rule(Some(methods.into_iter().collect()), path, policy)
+    }
+
+    /// Apply `policy` to every request of `method`
+    pub fn method(self, method: Method, policy: RoutePolicy) -> Self {
+        self.route_methods([method], "/**", policy)
+    }
+
+    /// Apply `policy` to requests matching no rule in place of `RoutePolicy::require`
+    pub fn fallback(self, fallback: RoutePolicy) -> Self {
+        Self { fallback, ..self }
+    }
+
+    /// The policy of the first rule matching the request
+    pub fn resolve(&self, method: &Method, path: &str) -> &RoutePolicy {
+        self.rules
+            .iter()
+            .find(|rule| {
+                rule.methods
+                    .as_ref()
+                    .is_none_or(|methods| methods.contains(method))
+                    && matches_path(&rule.path, path)
+            })
+            .map(|rule| &rule.policy)
+            .unwrap_or(&self.fallback)
+    }
+
+    fn rule(mut self, methods: Option<HashSet<Method>>, path: &str, policy: RoutePolicy) -> Self {
+        self.rules.push(Rule {
+            methods,
+            path: segments(path).map(str::to_string).collect(),
+            policy,
+        });
+        self
+    }
+}
+
+fn segments(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|segment| !segment.is_empty())
+}
+
+fn matches_path(pattern: &[String], path: &str) -> bool {
+    let mut path = segments(path);
+    for (i, expected) in pattern.iter().enumerate() {
+        if expected == "**" && i == pattern.len() - 1 {
+            return true;
+        }
+        let Some(segment) = path.next() else {
+            return false;
+        };
+        let wildcard = expected == "*" || (expected.starts_with('{') && expected.ends_with('}'));
+        if !wildcard && expected != segment {
+            return false;
+        }
+    }
+    path.next().is_none()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, path: &str) -> bool {
+        let pattern: Vec<String> = segments(pattern).map(str::to_string).collect();
+        matches_path(&pattern, path)
+    }
+
+    #[test]
+    fn matches_literal_paths() {
+        assert!(matches("/health", "/health"));
+        assert!(matches("/health", "/health/"));
+        assert!(matches("/", "/"));
+        assert!(!matches("/health", "/healthz"));
+        assert!(!matches("/health", "/health/live"));
+        assert!(!matches("/health/live", "/health"));
+    }
+
+    #[test]
+    fn matches_single_segment_wildcards() {
+        assert!(matches("/users/*", "/users/1"));
+        assert!(matches("/users/{id}/orders", "/users/1/orders"));
+        assert!(!matches("/users/*", "/users"));
+        assert!(!matches("/users/*", "/users/1/orders"));
+        assert!(!matches("/users/{id}/orders", "/users/1/invoices"));
+    }
+
+    #[test]
+    fn matches_trailing_multi_segment_wildcard() {
+        assert!(matches("/api/**", "/api"));
+        assert!(matches("/api/**", "/api/v1/users"));
+        assert!(matches("/**", "/"));
+        assert!(matches("/**", "/anything/at/all"));
+        assert!(!matches("/api/**", "/admin/v1"));
+        // Only a trailing `**` spans segments
+        assert!(!matches("/**/users", "/api/v1/users"));
+    }
+
+    #[test]
+    fn resolves_first_matching_rule_by_method_and_path() {
+        let policy = Policy::new()
+            .route("/health", RoutePolicy::exempt())
+            .method(Method::OPTIONS, RoutePolicy::exempt())
+            .route_methods(
+                [Method::POST],
+                "/orders/**",
+                RoutePolicy::require().limited_use(),
+            )
+            .route("/orders/**", RoutePolicy::require().without_bearer_bypass())
+            .fallback(RoutePolicy::require());
+
+        assert!(policy.resolve(&Method::GET, "/health").is_exempt());
+        assert!(policy.resolve(&Method::OPTIONS, "/orders/1").is_exempt());
+        assert!(policy
+            .resolve(&Method::POST, "/orders/1")
+            .requires_limited_use());
+        let get_order = policy.resolve(&Method::GET, "/orders/1");
+        assert!(!get_order.requires_limited_use());
+        assert!(!get_order.allows_bearer_bypass());
+        let fallback = policy.resolve(&Method::GET, "/users");
+        assert!(!fallback.is_exempt());
+        assert!(fallback.allows_bearer_bypass());
+    }
+}
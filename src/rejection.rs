@@ -0,0 +1,87 @@
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use std::fmt;
+
+/// Why `AppCheckService` rejected a request, named in the `reason` label of the rejection metric
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Rejection {
+    /// No token in the App Check header
+    MissingToken,
+    /// The token header couldn't be decoded
+    MissingMetadata,
+    /// The token header `alg` isn't allowed or its `typ` isn't `JWT`
+    InvalidAlgoSig,
+    /// The token header has no `kid`
+    MissingKid,
+    /// The token signature or claims failed verification
+    InvalidToken,
+    /// The token issuer and audience match no configured project
+    InvalidProject,
+    /// The token subject isn't an allowed app ID
+    InvalidAppId,
+    /// No ID token in the `Authorization` header where one is required
+    MissingIdToken,
+    /// The ID token failed verification
+    InvalidIdToken,
+    /// The limited-use token was already consumed
+    ReplayedToken,
+    /// The token couldn't be checked for replay
+    ReplayCheckFailed,
+    /// The token couldn't be consumed with the Firebase App Check API
+    ConsumeFailed,
+}
+
+impl Rejection {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::MissingToken => "missing-token",
+            Self::MissingMetadata => "missing-metadata",
+            Self::InvalidAlgoSig => "invalid-algo-sig",
+            Self::MissingKid => "missing-kid",
+            Self::InvalidToken => "invalid-token",
+            Self::InvalidProject => "invalid-project",
+            Self::InvalidAppId => "invalid-app-id",
+            Self::MissingIdToken => "missing-id-token",
+            Self::InvalidIdToken => "invalid-id-token",
+            Self::ReplayedToken => "replayed-token",
+            Self::ReplayCheckFailed => "replay-check-failed",
+            Self::ConsumeFailed => "consume-failed",
+        }
+    }
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The default response to a rejected request; a 401 which doesn't disclose the reason
+impl IntoResponse for Rejection {
+    fn into_response(self) -> Response {
+        let err_resp = serde_json::json!({
+            "status": "fail",
+            "message": "request not authenticated",
+        });
+        (StatusCode::UNAUTHORIZED, Json(err_resp)).into_response()
+    }
+}
+
+/// Builds the response to a request rejected by `AppCheckService`, e.g. an RFC 7807 problem
+/// document, a `WWW-Authenticate` challenge or a 403 for a disallowed app. Implemented for
+/// closures taking the `Rejection`.
+pub trait RejectionHandler: Send + Sync {
+    fn respond(&self, rejection: Rejection) -> Response;
+}
+
+impl<F> RejectionHandler for F
+where
+    F: Fn(Rejection) -> Response + Send + Sync,
+{
+    fn respond(&self, rejection: Rejection) -> Response {
+        self(rejection)
+    }
+}
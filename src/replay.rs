@@ -0,0 +1,114 @@
+use super::Error;
+use futures_util::future::BoxFuture;
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime},
+};
+
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Records consumed tokens so that limited-use tokens are only accepted once
+pub trait ReplayStore: Send + Sync {
+    /// Records the token until `expires_at`, returning whether it was newly recorded; `false`
+    /// means the token was already consumed and is being replayed
+    fn consume<'a>(
+        &'a self,
+        token_id: &'a str,
+        expires_at: SystemTime,
+    ) -> BoxFuture<'a, Result<bool, Error>>;
+}
+
+/// Holds consumed tokens in memory until they expire, sweeping expired tokens at most once a
+/// minute. Tokens are only protected from replay against the same instance of the application.
+#[derive(Default)]
+pub struct MemoryReplayStore {
+    state: Mutex<MemoryState>,
+}
+
+#[derive(Default)]
+struct MemoryState {
+    tokens: HashMap<String, SystemTime>,
+    last_sweep: Option<Instant>,
+}
+
+impl MemoryReplayStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl ReplayStore for MemoryReplayStore {
+    fn consume<'a>(
+        &'a self,
+        token_id: &'a str,
+        expires_at: SystemTime,
+    ) -> BoxFuture<'a, Result<bool, Error>> {
+        Box::pin(async move {
+            let mut state = self
+                .state
+                .lock()
+                .map_err(|err| Error::ReplayStore(err.to_string()))?;
+            let now = SystemTime::now();
+            if state
+                .last_sweep
+                .is_none_or(|last_sweep| last_sweep.elapsed() >= SWEEP_INTERVAL)
+            {
+                state.tokens.retain(|_, expiry| *expiry > now);
+                state.last_sweep = Some(Instant::now());
+            }
+
+            let replayed = state
+                .tokens
+                .get(token_id)
+                .is_some_and(|expiry| *expiry > now);
+            if !replayed {
+                state.tokens.insert(token_id.to_string(), expires_at);
+            }
+            metrics::gauge!("appcheck-replay-store-tokens").set(state.tokens.len() as f64);
+            Ok(!replayed)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn rejects_tokens_replayed_before_expiry() {
+        let store = MemoryReplayStore::new();
+        let expires_at = SystemTime::now() + Duration::from_secs(60);
+
+        assert!(store.consume("token", expires_at).await.unwrap());
+        assert!(!store.consume("token", expires_at).await.unwrap());
+        assert!(store.consume("other", expires_at).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn accepts_tokens_again_once_expired() {
+        let store = MemoryReplayStore::new();
+        let expired = SystemTime::now() - Duration::from_secs(1);
+
+        assert!(store.consume("token", expired).await.unwrap());
+        assert!(store.consume("token", expired).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn sweeps_expired_tokens() {
+        let store = MemoryReplayStore::new();
+        store
+            .consume("expired", SystemTime::now() - Duration::from_secs(1))
+            .await
+            .unwrap();
+        store.state.lock().unwrap().last_sweep = None;
+        store
+            .consume("token", SystemTime::now() + Duration::from_secs(60))
+            .await
+            .unwrap();
+
+        let state = store.state.lock().unwrap();
+        assert!(!state.tokens.contains_key("expired"));
+        assert!(state.tokens.contains_key("token"));
+    }
+}
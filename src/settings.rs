@@ -1,18 +1,102 @@
+use crate::{
+    jwk_cache::{Algorithm, KeyFormat},
+    Error,
+};
 use jwt_simple::{common::VerificationOptions, prelude::Duration};
 use serde::Deserialize;
-use std::collections::HashSet;
+use std::{collections::HashSet, path::PathBuf};
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct Settings {
-    /// The URL to retrieve rotating jwks from Firebase
+    /// The URL to retrieve rotating jwks or x509 certificates from; default is the Firebase App
+    /// Check jwks, in place of which the Firebase Authentication certificates are retrieved if
+    /// only `project_id` is set. `file://` URLs read the keys from a document on the local
+    /// filesystem instead
     #[serde(default = "default_jwk_url")]
     pub url: String,
-    /// The amount of time to cache fetched keys in hours
+    /// The format of the keys at `url`; default is x509 certificates for the Firebase
+    /// Authentication certificates URL and a jwks document otherwise
+    pub key_format: Option<KeyFormat>,
+    /// The OpenID Connect discovery document URL of the token issuer, from which the jwks URL is
+    /// resolved in place of `url`. The discovered issuer is only allowed if no `issuers`, App
+    /// Check projects or `project_id` are set, which otherwise take precedence
+    pub discovery_url: Option<String>,
+    /// How often in seconds to re-read the discovery document; default is 24 hours
+    #[serde(default = "default_discovery_recheck")]
+    pub discovery_recheck_secs: u64,
+    /// Timeout in seconds for requests to the jwks URL; default is 30 sec
+    #[serde(default = "default_request_timeout")]
+    pub request_timeout_secs: u64,
+    /// Timeout in seconds for connecting to the jwks URL host
+    pub connect_timeout_secs: Option<u64>,
+    /// Proxy URL to route requests to the jwks URL through
+    pub proxy_url: Option<String>,
+    /// Re-read a `file://` jwks URL every `min_duration_secs` to pick up changes
+    #[serde(default)]
+    pub watch_file: bool,
+    /// The maximum amount of time to cache fetched keys in hours; a shorter Cache-Control
+    /// max-age on the jwks response takes precedence, though neither can undercut
+    /// `min_duration_secs`
     #[serde(default = "default_cache_duration")]
     pub duration: u64,
-    /// Firebase project number
-    pub project_num: u64,
-    /// The list of allowed app IDs to gate authentication
+    /// The minimum amount of time to cache fetched keys in seconds, regardless of the
+    /// Cache-Control max-age on the jwks response; default is 5 min
+    #[serde(default = "default_min_cache_duration")]
+    pub min_duration_secs: u64,
+    /// The minimum amount of time in seconds between on-demand refreshes of the cached keys
+    /// triggered by tokens presenting an unknown kid, with requests made within it deferred to
+    /// its end; default is 30 sec
+    #[serde(default = "default_refresh_cooldown")]
+    pub refresh_cooldown_secs: u64,
+    /// How long in millis a request presenting an unknown kid waits for the on-demand refresh
+    /// before being rejected; requests are rejected immediately if unset
+    pub unknown_kid_wait_ms: Option<u64>,
+    /// The initial delay in seconds before retrying a failed refresh of the cached keys, doubled
+    /// with jitter for each consecutive failure; default is 5 sec
+    #[serde(default = "default_retry_initial")]
+    pub retry_initial_secs: u64,
+    /// The maximum delay in seconds between retries of a failed refresh; default is 5 min
+    #[serde(default = "default_retry_max")]
+    pub retry_max_secs: u64,
+    /// Accept a refreshed key set with no compatible keys in place of the current keys, which
+    /// rejects every token until the next refresh; default is to treat it as a failed refresh
+    #[serde(default)]
+    pub allow_empty_key_set: bool,
+    /// How long in seconds to keep accepting keys removed from the key set by a refresh, so
+    /// tokens signed by them before the rotation still verify
+    pub removed_key_grace_secs: Option<u64>,
+    /// Whether to fail or continue with an empty or seeded key set when the initial fetch of
+    /// the cached keys fails; default is to fail
+    #[serde(default)]
+    pub startup: StartupMode,
+    /// Path to persist each successfully fetched jwks key set to, used to bootstrap the cache
+    /// when the initial fetch fails
+    pub key_file: Option<PathBuf>,
+    /// The maximum age in seconds of a persisted key set to bootstrap from; default is 24 hours
+    #[serde(default = "default_key_file_max_age")]
+    pub key_file_max_age_secs: u64,
+    /// The token signature algorithms to accept; keys for other algorithms are dropped from
+    /// the key set. Default is RS256 as used by Firebase App Check
+    #[serde(default = "default_algorithms")]
+    pub algorithms: HashSet<Algorithm>,
+    /// Firebase project number, from which the App Check token issuer and audience are derived
+    /// unless `issuers` or `audiences` are set
+    pub project_num: Option<u64>,
+    /// Further Firebase projects to accept App Check tokens for, each with its own app ID
+    /// allowlist, sharing the jwks of `project_num`
+    #[serde(default)]
+    pub projects: Vec<ProjectSettings>,
+    /// Firebase project ID for verifying Firebase Authentication ID tokens, from which the
+    /// issuer and audience are derived when no App Check projects, `issuers` or `audiences`
+    /// are set
+    pub project_id: Option<String>,
+    /// The `iss` claim values to accept, for verifying tokens from issuers other than Firebase;
+    /// required if no App Check projects, `project_id` or `discovery_url` are set
+    pub issuers: Option<HashSet<String>>,
+    /// The `aud` claim values to accept; required if no App Check projects or `project_id` are
+    /// set
+    pub audiences: Option<HashSet<String>>,
+    /// The list of allowed app IDs of `project_num` to gate authentication
     pub app_ids: Option<HashSet<String>>,
     /// Reject tokens created more than max_validity seconds ago
     pub max_validity_secs: Option<u64>,
@@ -20,10 +104,69 @@ pub struct Settings {
     pub time_tolerance_secs: Option<u64>,
     /// Accept tokens created in the future
     pub accept_future: Option<bool>,
+    /// Whether `AppCheckLayer` rejects, only reports or skips checking unauthorized requests;
+    /// default is to reject
+    #[serde(default)]
+    pub enforcement: EnforcementMode,
     /// Bypass AppCheck with self-issued JWT bearer tokens
     pub bearer: Option<BearerSettings>,
 }
 
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum StartupMode {
+    /// Return an error from the cache constructor if the initial key set can't be fetched
+    #[default]
+    Strict,
+    /// Start with an empty or seeded key set and keep retrying the fetch in the background
+    Lazy,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EnforcementMode {
+    /// Reject requests failing any check with a 401
+    #[default]
+    Enforce,
+    /// Run every check and record the verdict, but let requests failing them through
+    ReportOnly,
+    /// Skip the checks entirely
+    Off,
+}
+
+impl EnforcementMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Enforce => "enforce",
+            Self::ReportOnly => "report-only",
+            Self::Off => "off",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+pub struct ProjectSettings {
+    /// Firebase project number
+    pub project_num: u64,
+    /// The list of allowed app IDs of the project
+    pub app_ids: Option<HashSet<String>>,
+}
+
+impl ProjectSettings {
+    /// The `iss` claim of App Check tokens for the project
+    pub fn issuer(&self) -> String {
+        format!(
+            "https://firebaseappcheck.googleapis.com/{}",
+            self.project_num
+        )
+    }
+
+    /// The `aud` claim of App Check tokens for the project
+    pub fn audience(&self) -> String {
+        format!("projects/{}", self.project_num)
+    }
+}
+
 #[derive(Clone, Debug, Deserialize)]
 pub struct BearerSettings {
     /// Base58 encoded string of the Ed25519 Public Key verifier
@@ -32,19 +175,172 @@ pub struct BearerSettings {
     pub allowlist: Vec<String>,
 }
 
+fn default_jwk_url() -> String {
+    APP_CHECK_JWKS_URL.to_string()
+}
+
 fn default_cache_duration() -> u64 {
     6
 }
 
-fn default_jwk_url() -> String {
-    "https://firebaseappcheck.googleapis.com/v1/jwks".to_string()
+fn default_min_cache_duration() -> u64 {
+    300
+}
+
+fn default_refresh_cooldown() -> u64 {
+    30
+}
+
+fn default_retry_initial() -> u64 {
+    5
+}
+
+fn default_retry_max() -> u64 {
+    300
+}
+
+fn default_key_file_max_age() -> u64 {
+    24 * 60 * 60
+}
+
+fn default_discovery_recheck() -> u64 {
+    24 * 60 * 60
 }
 
+fn default_request_timeout() -> u64 {
+    30
+}
+
+fn default_algorithms() -> HashSet<Algorithm> {
+    HashSet::from([Algorithm::RS256])
+}
+
+const APP_CHECK_JWKS_URL: &str = "https://firebaseappcheck.googleapis.com/v1/jwks";
+const ID_TOKEN_CERTS_URL: &str =
+    "https://www.googleapis.com/robot/v1/metadata/x509/securetoken@system.gserviceaccount.com";
+
 impl Settings {
+    /// The configured key URL, or that of the Firebase token type being verified when the
+    /// default is left in place
+    pub fn url(&self) -> &str {
+        if self.url == APP_CHECK_JWKS_URL
+            && self.project_id.is_some()
+            && self.app_check_projects().is_empty()
+        {
+            return ID_TOKEN_CERTS_URL;
+        }
+        &self.url
+    }
+
+    pub fn key_format(&self) -> KeyFormat {
+        self.key_format
+            .unwrap_or(if self.url() == ID_TOKEN_CERTS_URL {
+                KeyFormat::X509
+            } else {
+                KeyFormat::Jwks
+            })
+    }
+
     pub fn duration(&self) -> tokio::time::Duration {
         tokio::time::Duration::from_secs(60 * 60 * self.duration)
     }
 
+    pub fn min_duration(&self) -> tokio::time::Duration {
+        tokio::time::Duration::from_secs(self.min_duration_secs)
+    }
+
+    /// Builds the client for fetching the jwks URL from the configured timeouts and proxy
+    pub fn http_client(&self) -> Result<reqwest::Client, Error> {
+        let builder = client_builder(self.request_timeout_secs);
+        let builder = if let Some(connect_timeout) = self.connect_timeout_secs {
+            builder.connect_timeout(tokio::time::Duration::from_secs(connect_timeout))
+        } else {
+            builder
+        };
+        let builder = if let Some(ref proxy_url) = self.proxy_url {
+            builder.proxy(reqwest::Proxy::all(proxy_url)?)
+        } else {
+            builder
+        };
+        Ok(builder.build()?)
+    }
+
+    pub fn discovery_recheck(&self) -> tokio::time::Duration {
+        tokio::time::Duration::from_secs(self.discovery_recheck_secs)
+    }
+
+    pub fn file_watch(&self) -> Option<tokio::time::Duration> {
+        self.watch_file.then(|| self.min_duration())
+    }
+
+    pub fn refresh_cooldown(&self) -> tokio::time::Duration {
+        tokio::time::Duration::from_secs(self.refresh_cooldown_secs)
+    }
+
+    pub fn unknown_kid_wait(&self) -> Option<tokio::time::Duration> {
+        self.unknown_kid_wait_ms
+            .map(tokio::time::Duration::from_millis)
+    }
+
+    pub fn retry_initial(&self) -> tokio::time::Duration {
+        tokio::time::Duration::from_secs(self.retry_initial_secs)
+    }
+
+    pub fn retry_max(&self) -> tokio::time::Duration {
+        tokio::time::Duration::from_secs(self.retry_max_secs)
+    }
+
+    pub fn removed_key_grace(&self) -> Option<tokio::time::Duration> {
+        self.removed_key_grace_secs
+            .map(tokio::time::Duration::from_secs)
+    }
+
+    pub fn key_file_max_age(&self) -> tokio::time::Duration {
+        tokio::time::Duration::from_secs(self.key_file_max_age_secs)
+    }
+
+    /// The App Check projects to accept tokens for; `project_num` with `app_ids` and `projects`
+    pub fn app_check_projects(&self) -> Vec<ProjectSettings> {
+        self.project_num
+            .map(|project_num| ProjectSettings {
+                project_num,
+                app_ids: self.app_ids.clone(),
+            })
+            .into_iter()
+            .chain(self.projects.iter().cloned())
+            .collect()
+    }
+
+    /// The configured issuers, or the Firebase App Check issuers of the projects or the Firebase
+    /// Authentication issuer of the project ID
+    pub fn allowed_issuers(&self) -> Option<HashSet<String>> {
+        if self.issuers.is_some() {
+            return self.issuers.clone();
+        }
+        let projects = self.app_check_projects();
+        if !projects.is_empty() {
+            return Some(projects.iter().map(ProjectSettings::issuer).collect());
+        }
+        self.project_id.as_ref().map(|project_id| {
+            HashSet::from([format!("https://securetoken.google.com/{project_id}")])
+        })
+    }
+
+    /// The configured audiences, or the Firebase App Check audiences of the projects or the
+    /// Firebase Authentication audience of the project ID
+    pub fn allowed_audiences(&self) -> Option<HashSet<String>> {
+        if self.audiences.is_some() {
+            return self.audiences.clone();
+        }
+        let projects = self.app_check_projects();
+        if !projects.is_empty() {
+            return Some(projects.iter().map(ProjectSettings::audience).collect());
+        }
+        self.project_id
+            .as_ref()
+            .map(|project_id| HashSet::from([project_id.clone()]))
+    }
+
     pub fn max_validity(&self) -> Option<Duration> {
         self.max_validity_secs.map(Duration::from_secs)
     }
@@ -52,34 +348,129 @@ impl Settings {
     pub fn time_tolerance(&self) -> Option<Duration> {
         self.time_tolerance_secs.map(Duration::from_secs)
     }
-}
 
-impl From<Settings> for VerificationOptions {
-    fn from(settings: Settings) -> Self {
+    // The options to verify tokens with, which check no issuer or audience if none is configured
+    pub(crate) fn verification_options(&self) -> VerificationOptions {
         let default = VerificationOptions::default();
-        let max_validity = if settings.max_validity().is_some() {
-            settings.max_validity()
+        let max_validity = if self.max_validity().is_some() {
+            self.max_validity()
         } else {
             default.max_validity
         };
-        let time_tolerance = if settings.time_tolerance().is_some() {
-            settings.time_tolerance()
+        let time_tolerance = if self.time_tolerance().is_some() {
+            self.time_tolerance()
         } else {
             default.time_tolerance
         };
         VerificationOptions {
-            accept_future: settings.accept_future.unwrap_or(default.accept_future),
+            accept_future: self.accept_future.unwrap_or(default.accept_future),
             max_validity,
             time_tolerance,
-            allowed_issuers: Some(HashSet::from([format!(
-                "https://firebaseappcheck.googleapis.com/{}",
-                settings.project_num
-            )])),
-            allowed_audiences: Some(HashSet::from([format!(
-                "projects/{}",
-                settings.project_num
-            )])),
+            allowed_issuers: self.allowed_issuers(),
+            allowed_audiences: self.allowed_audiences(),
             ..default
         }
     }
 }
+
+fn client_builder(request_timeout_secs: u64) -> reqwest::ClientBuilder {
+    reqwest::Client::builder().timeout(tokio::time::Duration::from_secs(request_timeout_secs))
+}
+
+// The client for fetching keys without settings, with the default request timeout so a stalled
+// jwks endpoint can't hang a refresh indefinitely
+pub(crate) fn default_http_client() -> Result<reqwest::Client, Error> {
+    Ok(client_builder(default_request_timeout()).build()?)
+}
+
+/// Fails if the settings configure no issuers or audiences to check tokens against; the issuer
+/// resolved from a `discovery_url` is only allowed by a `JwkCache` built from the settings
+impl TryFrom<Settings> for VerificationOptions {
+    type Error = Error;
+
+    fn try_from(settings: Settings) -> Result<Self, Self::Error> {
+        let verify_opts = settings.verification_options();
+        check_verification_options(&verify_opts)?;
+        Ok(verify_opts)
+    }
+}
+
+// Without allowed issuers and audiences any token signed by a key in the set would be accepted,
+// including tokens minted by the issuer for other clients
+pub(crate) fn check_verification_options(verify_opts: &VerificationOptions) -> Result<(), Error> {
+    if verify_opts.allowed_issuers.is_none() {
+        return Err(Error::MissingIssuer);
+    }
+    if verify_opts.allowed_audiences.is_none() {
+        return Err(Error::MissingAudience);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(value: serde_json::Value) -> Settings {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn derives_issuer_and_audience_from_project_number() {
+        let verify_opts =
+            VerificationOptions::try_from(settings(serde_json::json!({ "project_num": 123 })))
+                .unwrap();
+        assert_eq!(
+            verify_opts.allowed_issuers,
+            Some(HashSet::from([
+                "https://firebaseappcheck.googleapis.com/123".to_string()
+            ]))
+        );
+        assert_eq!(
+            verify_opts.allowed_audiences,
+            Some(HashSet::from(["projects/123".to_string()]))
+        );
+    }
+
+    #[test]
+    fn requires_issuers_and_audiences() {
+        let result = VerificationOptions::try_from(settings(serde_json::json!({})));
+        assert!(matches!(result, Err(Error::MissingIssuer)));
+
+        let result = VerificationOptions::try_from(settings(serde_json::json!({
+            "issuers": ["https://issuer.example.com"],
+        })));
+        assert!(matches!(result, Err(Error::MissingAudience)));
+    }
+
+    #[test]
+    fn derives_id_token_url_only_in_place_of_default() {
+        let app_check = settings(serde_json::json!({ "project_num": 123 }));
+        assert_eq!(app_check.url(), APP_CHECK_JWKS_URL);
+
+        let id_token = settings(serde_json::json!({ "project_id": "project" }));
+        assert_eq!(id_token.url(), ID_TOKEN_CERTS_URL);
+
+        let configured = settings(serde_json::json!({
+            "project_id": "project",
+            "url": "file:///etc/keys.json",
+        }));
+        assert_eq!(configured.url(), "file:///etc/keys.json");
+    }
+
+    #[test]
+    fn selects_key_format_by_url() {
+        let app_check = settings(serde_json::json!({ "project_num": 123 }));
+        assert_eq!(app_check.key_format(), KeyFormat::Jwks);
+
+        let id_token = settings(serde_json::json!({ "project_id": "project" }));
+        assert_eq!(id_token.key_format(), KeyFormat::X509);
+
+        let configured = settings(serde_json::json!({
+            "project_id": "project",
+            "url": "file:///etc/certs.json",
+            "key_format": "x509",
+        }));
+        assert_eq!(configured.key_format(), KeyFormat::X509);
+    }
+}
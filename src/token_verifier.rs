@@ -1,23 +1,45 @@
-use super::{bearer::BearerVerifier, settings::BearerSettings, Error};
+use super::{
+    bearer::BearerVerifier,
+    consumer::{TokenConsumer, TokenConsumption},
+    id_token::IdTokenClaims,
+    jwk_cache::{Algorithm, KeySetStatus, RefreshTrigger, VerifyingKey},
+    settings::{BearerSettings, EnforcementMode, ProjectSettings},
+    AppCheckClaims, Error,
+};
 use jwt_simple::{
-    algorithms::{RS256PublicKey, RSAPublicKeyLike},
     claims::{JWTClaims, NoCustomClaims},
     common::VerificationOptions,
+    prelude::Clock,
 };
+use serde::{de::DeserializeOwned, Serialize};
 use std::collections::{HashMap, HashSet};
-use tokio::sync::watch;
+use tokio::{sync::watch, time::Duration};
+
+/// The App Check project a verified token was issued for, inserted into the request extensions
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AppCheckProject {
+    pub project_num: u64,
+}
 
 #[derive(Clone)]
 pub struct TokenVerifier {
-    jwks: watch::Receiver<HashMap<String, RS256PublicKey>>,
+    jwks: watch::Receiver<HashMap<String, VerifyingKey>>,
     verify_opts: VerificationOptions,
     app_ids: Option<HashSet<String>>,
+    projects: Vec<ProjectSettings>,
+    algorithms: HashSet<Algorithm>,
+    refresh: Option<RefreshTrigger>,
+    unknown_kid_wait: Option<Duration>,
+    status: Option<watch::Receiver<KeySetStatus>>,
+    discovered_issuer: Option<watch::Receiver<Option<String>>>,
+    consumer: Option<TokenConsumer>,
+    enforcement: EnforcementMode,
     pub bearer_verifier: Option<BearerVerifier>,
 }
 
 impl TokenVerifier {
     pub fn new(
-        jwks: watch::Receiver<HashMap<String, RS256PublicKey>>,
+        jwks: watch::Receiver<HashMap<String, VerifyingKey>>,
         verify_opts: VerificationOptions,
         app_ids: Option<HashSet<String>>,
         bearer_settings: Option<BearerSettings>,
@@ -32,29 +54,326 @@ impl TokenVerifier {
             jwks,
             verify_opts,
             app_ids,
+            projects: Vec::new(),
+            algorithms: HashSet::from([Algorithm::RS256]),
+            refresh: None,
+            unknown_kid_wait: None,
+            status: None,
+            discovered_issuer: None,
+            consumer: None,
+            enforcement: EnforcementMode::default(),
             bearer_verifier,
         })
     }
 
+    /// Match verified tokens to one of `projects` by issuer and audience, checking the subject
+    /// against the app IDs of the matched project in place of the verifier's own app IDs
+    pub fn with_projects(self, projects: Vec<ProjectSettings>) -> Self {
+        Self { projects, ..self }
+    }
+
+    /// The configured project whose issuer and audience the verified claims were issued for
+    pub fn match_project<CustomClaims>(
+        &self,
+        claims: &JWTClaims<CustomClaims>,
+    ) -> Option<&ProjectSettings> {
+        self.projects.iter().find(|project| {
+            claims.issuer.as_deref() == Some(project.issuer().as_str())
+                && claims.audiences.as_ref().is_some_and(|audiences| {
+                    audiences.contains(&HashSet::from([project.audience()]))
+                })
+        })
+    }
+
+    /// Whether the verifier matches tokens to configured projects
+    pub fn has_projects(&self) -> bool {
+        !self.projects.is_empty()
+    }
+
+    /// Accept tokens signed with any of `algorithms` in place of the default RS256
+    pub fn with_algorithms(self, algorithms: HashSet<Algorithm>) -> Self {
+        Self { algorithms, ..self }
+    }
+
+    pub fn allows_algorithm(&self, alg: &str) -> bool {
+        Algorithm::from_name(alg).is_some_and(|alg| self.algorithms.contains(&alg))
+    }
+
+    /// Signal `refresh` whenever a token presents an unknown kid, optionally waiting up to
+    /// `unknown_kid_wait` for the refreshed key set in `verify_token_with_refresh`
+    pub fn with_refresh(self, refresh: RefreshTrigger, unknown_kid_wait: Option<Duration>) -> Self {
+        Self {
+            refresh: Some(refresh),
+            unknown_kid_wait,
+            ..self
+        }
+    }
+
+    /// The trigger for refreshing the key set of the backing `JwkCache`, if any
+    pub fn refresh_trigger(&self) -> Option<&RefreshTrigger> {
+        self.refresh.as_ref()
+    }
+
+    pub(crate) fn with_status(self, status: watch::Receiver<KeySetStatus>) -> Self {
+        Self {
+            status: Some(status),
+            ..self
+        }
+    }
+
+    pub(crate) fn with_discovered_issuer(self, issuer: watch::Receiver<Option<String>>) -> Self {
+        Self {
+            discovered_issuer: Some(issuer),
+            ..self
+        }
+    }
+
+    /// Consume each locally verified token with the Firebase App Check API through `consumer`
+    /// in `verify_and_consume_token` and `AppCheckLayer`, e.g. for a verifier guarding
+    /// high-value routes that accept limited-use tokens
+    pub fn with_consumer(self, consumer: TokenConsumer) -> Self {
+        Self {
+            consumer: Some(consumer),
+            ..self
+        }
+    }
+
+    pub fn consumer(&self) -> Option<&TokenConsumer> {
+        self.consumer.as_ref()
+    }
+
+    /// The mode an `AppCheckLayer` constructed with the verifier starts in, as configured by the
+    /// `enforcement` setting of a cache built from `Settings`
+    pub fn with_enforcement_mode(self, enforcement: EnforcementMode) -> Self {
+        Self {
+            enforcement,
+            ..self
+        }
+    }
+
+    pub fn enforcement_mode(&self) -> EnforcementMode {
+        self.enforcement
+    }
+
+    /// Reports the loaded kids and refresh health published by the backing `JwkCache`; a
+    /// verifier constructed without a cache only reports its loaded kids
+    pub fn status(&self) -> KeySetStatus {
+        match self.status {
+            Some(ref status) => status.borrow().clone(),
+            None => KeySetStatus {
+                kids: self.jwks.borrow().keys().cloned().collect(),
+                ..Default::default()
+            },
+        }
+    }
+
     pub fn verify_token(
         &self,
         key_id: &str,
         token: &str,
         options: VerificationOptions,
     ) -> Result<JWTClaims<NoCustomClaims>, Error> {
-        self.jwks
-            .borrow()
+        self.verify_cached(key_id, token, options)
+    }
+
+    /// Verifies the token like `verify_token`, but when the kid is unknown waits a bounded time
+    /// for the refresh it triggers to publish a new key set before rejecting the token
+    pub async fn verify_token_with_refresh(
+        &self,
+        key_id: &str,
+        token: &str,
+        options: VerificationOptions,
+    ) -> Result<JWTClaims<NoCustomClaims>, Error> {
+        self.verify_with_refresh(key_id, token, options).await
+    }
+
+    /// Verifies the token like `verify_token_with_refresh`, then consumes it with the configured
+    /// `TokenConsumer`, if any, reporting whether an earlier request had already consumed it
+    pub async fn verify_and_consume_token(
+        &self,
+        key_id: &str,
+        token: &str,
+        options: VerificationOptions,
+    ) -> Result<(AppCheckClaims, Option<TokenConsumption>), Error> {
+        let claims = self.verify_with_refresh(key_id, token, options).await?;
+        let project = self.match_project(&claims).map(|project| AppCheckProject {
+            project_num: project.project_num,
+        });
+        let claims = AppCheckClaims::new(claims, project);
+        let consumption = self.consume_token(token, &claims).await?;
+        Ok((claims, consumption))
+    }
+
+    /// Consumes a verified token with the configured `TokenConsumer` for the project it was
+    /// issued for; nothing is consumed without a consumer
+    pub async fn consume_token(
+        &self,
+        token: &str,
+        claims: &AppCheckClaims,
+    ) -> Result<Option<TokenConsumption>, Error> {
+        let Some(ref consumer) = self.consumer else {
+            return Ok(None);
+        };
+        let project_number = claims.project_number().ok_or_else(|| {
+            Error::ConsumeToken("token audience names no project number".to_string())
+        })?;
+        let consumption = consumer.consume(project_number, token).await?;
+        if consumption.already_consumed {
+            metrics::counter!("appcheck-token-already-consumed").increment(1);
+            tracing::debug!("app check token already consumed");
+        }
+        Ok(Some(consumption))
+    }
+
+    /// Verifies a Firebase Authentication ID token like `verify_token_with_refresh`, and that
+    /// the user authenticated in the past, within the configured time tolerance
+    pub async fn verify_id_token(
+        &self,
+        key_id: &str,
+        token: &str,
+    ) -> Result<JWTClaims<IdTokenClaims>, Error> {
+        let options = self.verify_opts();
+        let tolerance = options.time_tolerance.unwrap_or_default();
+        let claims: JWTClaims<IdTokenClaims> =
+            self.verify_with_refresh(key_id, token, options).await?;
+        let auth_time = claims.custom.auth_time;
+        if auth_time == 0 || auth_time > (Clock::now_since_epoch() + tolerance).as_secs() {
+            return Err(Error::InvalidAuthTime(auth_time));
+        }
+        Ok(claims)
+    }
+
+    async fn verify_with_refresh<CustomClaims: Serialize + DeserializeOwned>(
+        &self,
+        key_id: &str,
+        token: &str,
+        options: VerificationOptions,
+    ) -> Result<JWTClaims<CustomClaims>, Error> {
+        let (Some(refresh), Some(wait)) = (&self.refresh, self.unknown_kid_wait) else {
+            return self.verify_cached(key_id, token, options);
+        };
+
+        let mut jwks = self.jwks.clone();
+        if jwks.borrow_and_update().contains_key(key_id) {
+            return self.verify_with_keys(&jwks.borrow(), key_id, token, options);
+        }
+
+        refresh.trigger();
+        tracing::debug!(key_id, "awaiting jwk set refresh for unknown kid");
+        let _ = tokio::time::timeout(wait, jwks.changed()).await;
+        let jwks = jwks.borrow();
+        self.verify_with_keys(&jwks, key_id, token, options)
+    }
+
+    /// Whether the verifier holds any keys to validate tokens against; a lazily started cache
+    /// that has yet to complete a fetch without seeded keys is not ready
+    pub fn is_ready(&self) -> bool {
+        !self.jwks.borrow().is_empty()
+    }
+
+    // Verifies against the currently cached keys, triggering a refresh for an unknown kid
+    fn verify_cached<CustomClaims: Serialize + DeserializeOwned>(
+        &self,
+        key_id: &str,
+        token: &str,
+        options: VerificationOptions,
+    ) -> Result<JWTClaims<CustomClaims>, Error> {
+        let result = self.verify_with_keys(&self.jwks.borrow(), key_id, token, options);
+        if let (Err(Error::UnknownJwk(_)), Some(refresh)) = (&result, &self.refresh) {
+            refresh.trigger();
+        }
+        result
+    }
+
+    // Looks up the key by kid and verifies the token with it, which also requires the token
+    // header `alg` to match the algorithm of the key
+    fn verify_with_keys<CustomClaims: Serialize + DeserializeOwned>(
+        &self,
+        jwks: &HashMap<String, VerifyingKey>,
+        key_id: &str,
+        token: &str,
+        options: VerificationOptions,
+    ) -> Result<JWTClaims<CustomClaims>, Error> {
+        let pubkey = jwks
             .get(key_id)
-            .map(|pubkey| pubkey.verify_token(token, Some(options)))
-            .ok_or_else(|| Error::UnknownJwk(key_id.to_string()))?
+            .ok_or_else(|| Error::UnknownJwk(key_id.to_string()))?;
+        if !self.algorithms.contains(&pubkey.algorithm()) {
+            return Err(Error::DisallowedAlgorithm(pubkey.algorithm().to_string()));
+        }
+        pubkey
+            .verify_token(token, Some(options))
             .map_err(|err| err.into())
     }
 
+    /// The options to verify tokens with; without configured issuers the issuer resolved by
+    /// OIDC discovery is allowed, and no issuer is allowed until one has been resolved
     pub fn verify_opts(&self) -> VerificationOptions {
-        self.verify_opts.clone()
+        let mut verify_opts = self.verify_opts.clone();
+        if let (None, Some(issuer)) = (&verify_opts.allowed_issuers, &self.discovered_issuer) {
+            verify_opts.allowed_issuers = Some(issuer.borrow().iter().cloned().collect());
+        }
+        verify_opts
     }
 
     pub fn verify_app_ids(&self) -> Option<&HashSet<String>> {
         self.app_ids.as_ref()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jwt_simple::{
+        algorithms::{Ed25519KeyPair, EdDSAKeyPairLike},
+        claims::Claims,
+        prelude::Duration as JwtDuration,
+    };
+
+    fn verifier(key_pair: &Ed25519KeyPair) -> TokenVerifier {
+        let keys = HashMap::from([("kid".to_string(), key_pair.public_key().into())]);
+        let verify_opts = VerificationOptions {
+            allowed_issuers: Some(HashSet::from(["issuer".to_string()])),
+            allowed_audiences: Some(HashSet::from(["audience".to_string()])),
+            ..Default::default()
+        };
+        TokenVerifier::new(watch::channel(keys).1, verify_opts, None, None).unwrap()
+    }
+
+    fn token(key_pair: &Ed25519KeyPair) -> String {
+        let claims = Claims::create(JwtDuration::from_mins(5))
+            .with_issuer("issuer")
+            .with_audience("audience");
+        key_pair.sign(claims).unwrap()
+    }
+
+    #[test]
+    fn rejects_keys_of_disallowed_algorithms() {
+        let key_pair = Ed25519KeyPair::generate().with_key_id("kid");
+        let verifier = verifier(&key_pair);
+
+        assert!(!verifier.allows_algorithm("EdDSA"));
+        let result = verifier.verify_token("kid", &token(&key_pair), verifier.verify_opts());
+        assert!(matches!(result, Err(Error::DisallowedAlgorithm(_))));
+    }
+
+    #[test]
+    fn verifies_tokens_of_allowed_algorithms() {
+        let key_pair = Ed25519KeyPair::generate().with_key_id("kid");
+        let verifier = verifier(&key_pair).with_algorithms(HashSet::from([Algorithm::EdDSA]));
+
+        assert!(verifier.allows_algorithm("EdDSA"));
+        assert!(!verifier.allows_algorithm("RS256"));
+        assert!(!verifier.allows_algorithm("none"));
+        let result = verifier.verify_token("kid", &token(&key_pair), verifier.verify_opts());
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn rejects_unknown_kids() {
+        let key_pair = Ed25519KeyPair::generate().with_key_id("other");
+        let verifier = verifier(&key_pair).with_algorithms(HashSet::from([Algorithm::EdDSA]));
+
+        let result = verifier.verify_token("unknown", &token(&key_pair), verifier.verify_opts());
+        assert!(matches!(result, Err(Error::UnknownJwk(_))));
+    }
+}